@@ -0,0 +1,64 @@
+//! Round trip through the block, frame and streaming APIs, built as a WebAssembly module to check
+//! the wasm32-unknown-unknown support:
+//!
+//! ```text
+//! rustup target add wasm32-unknown-unknown
+//! cargo build --release --example wasm_roundtrip --target wasm32-unknown-unknown
+//! wasmtime run --invoke roundtrip \
+//!     target/wasm32-unknown-unknown/release/examples/wasm_roundtrip.wasm
+//! ```
+//!
+//! `roundtrip` returns 0 when every check passes, the number of the failed check otherwise.
+extern crate lz4;
+
+use lz4::block::{self, CompressionMode};
+use lz4::frame;
+use lz4::{BlockSize, CompressionLevel, Decoder, EncoderBuilder};
+use std::io::{Read, Write};
+
+#[no_mangle]
+pub extern "C" fn roundtrip() -> i32 {
+    let data: Vec<u8> = (0..300000u32)
+        .map(|i| (i % 251) as u8 ^ (i >> 12) as u8)
+        .collect();
+
+    let compressed = match block::compress(&data, Some(CompressionMode::HIGHCOMPRESSION(9)), true) {
+        Ok(compressed) => compressed,
+        Err(_) => return 1,
+    };
+    match block::decompress(&compressed, None) {
+        Ok(ref decompressed) if *decompressed == data => {}
+        _ => return 2,
+    }
+
+    let mut builder = EncoderBuilder::new();
+    builder
+        .block_size(BlockSize::Max64KB)
+        .compression_level(CompressionLevel::Fast(4));
+    let compressed = match frame::compress(&data, &builder) {
+        Ok(compressed) => compressed,
+        Err(_) => return 3,
+    };
+    match frame::decompress(&compressed) {
+        Ok(ref decompressed) if *decompressed == data => {}
+        _ => return 4,
+    }
+
+    // Streaming encoder and decoder, with content checksum
+    let mut encoder = match EncoderBuilder::new().build(Vec::new()) {
+        Ok(encoder) => encoder,
+        Err(_) => return 5,
+    };
+    if encoder.write_all(&data).is_err() {
+        return 6;
+    }
+    let compressed = match encoder.finish() {
+        (compressed, Ok(())) => compressed,
+        _ => return 7,
+    };
+    let mut decompressed = Vec::new();
+    match Decoder::new(&compressed[..]).and_then(|mut d| d.read_to_end(&mut decompressed)) {
+        Ok(_) if decompressed == data => 0,
+        _ => 8,
+    }
+}
@@ -1,8 +1,12 @@
+#[cfg(feature = "bindgen")]
+extern crate bindgen;
 extern crate cc;
+extern crate pkg_config;
 
 use std::{env, fs, process};
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 fn main() {
     match run() {
@@ -14,15 +18,226 @@ fn main() {
     }
 }
 
+// Oldest liblz4 matching the layouts and functions declared by the bindings
+const MIN_SYSTEM_VERSION: &str = "1.9.0";
+const MIN_SYSTEM_VERSION_NUMBER: u32 = 10900;
+
+// Oldest vendored liblz4 matching the static linking only APIs of the experimental feature
+const MIN_EXPERIMENTAL_VERSION_NUMBER: u32 = 10904;
+
+// Prefix of the xxHash copy built next to a system liblz4, see src/lib.rs
+const SYSTEM_XXH_NAMESPACE: &str = "lz4_sys_";
+
+// Prefix given to the xxHash symbols by the xxh-namespace feature, src/lib.rs links to the
+// prefixed names
+const XXH_NAMESPACE: &str = "LZ4_";
+
+// Environment variables tuning the vendored build, see configure()
+const OPT_LEVEL_VAR: &str = "LZ4_SYS_OPT_LEVEL";
+const MEMORY_USAGE_VAR: &str = "LZ4_SYS_MEMORY_USAGE";
+const FAST_DEC_LOOP_VAR: &str = "LZ4_SYS_FAST_DEC_LOOP";
+
 fn run() -> Result<(), Box<dyn Error>> {
+    println!("cargo:rerun-if-env-changed=LZ4_SYS_USE_PKG_CONFIG");
+    for var in &[OPT_LEVEL_VAR, MEMORY_USAGE_VAR, FAST_DEC_LOOP_VAR] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=liblz4");
+    println!("cargo:rerun-if-changed=src/prefix.h");
+    println!("cargo:rerun-if-changed=wasm-shim");
+    println!("cargo:rustc-check-cfg=cfg(lz4_sys_system)");
+    if use_pkg_config() {
+        if let Some(feature) = vendored_only_feature() {
+            println!(
+                "cargo:warning=the {} feature needs the vendored liblz4, ignoring the system one",
+                feature
+            );
+            return build_vendored();
+        }
+        match link_system() {
+            Ok(()) => return Ok(()),
+            // pkg-config errors span several lines, cargo only shows the first one
+            Err(err) => println!(
+                "cargo:warning=system liblz4 not usable, building the vendored one: {}",
+                err.to_string().split_whitespace().collect::<Vec<_>>().join(" ")
+            ),
+        }
+    }
+    build_vendored()
+}
+
+fn use_pkg_config() -> bool {
+    if env::var_os("CARGO_FEATURE_SYSTEM_LZ4").is_some() {
+        return true;
+    }
+    match env::var("LZ4_SYS_USE_PKG_CONFIG") {
+        Ok(value) => value != "" && value != "0",
+        Err(_) => false,
+    }
+}
+
+fn vendored_only_feature() -> Option<&'static str> {
+    // Static linking only APIs are not exported by shared builds, nor guaranteed to match the
+    // bindings in another liblz4 version
+    if env::var_os("CARGO_FEATURE_EXPERIMENTAL").is_some() {
+        return Some("experimental");
+    }
+    // The renamed symbols only exist in the vendored build
+    if env::var_os("CARGO_FEATURE_PREFIX_SYMBOLS").is_some() {
+        return Some("prefix-symbols");
+    }
+    None
+}
+
+/// Links the liblz4 found by pkg-config. Whether it is linked statically or dynamically follows
+/// the usual pkg-config variables (LIBLZ4_STATIC, LIBLZ4_DYNAMIC,
+/// PKG_CONFIG_ALL_STATIC, ...).
+fn link_system() -> Result<(), Box<dyn Error>> {
+    let library = pkg_config::Config::new()
+        .atleast_version(MIN_SYSTEM_VERSION)
+        .cargo_metadata(false)
+        .probe("liblz4")?;
+    // The pkg-config metadata may not match the library actually installed
+    check_system_version(&library)?;
+    pkg_config::Config::new()
+        .atleast_version(MIN_SYSTEM_VERSION)
+        .probe("liblz4")?;
+
+    // Shared builds of liblz4 don't export their copy of xxHash, and static ones may name it like
+    // any other copy of xxHash, so the bindings link to one of their own under a private prefix.
+    // It goes to its own directory, where no vendored liblz4.a from an earlier build can shadow
+    // the system library.
+    let out = PathBuf::from(env::var_os("OUT_DIR").ok_or("missing OUT_DIR environment variable")?);
+    let mut compiler = cc::Build::new();
+    compiler
+        .file("liblz4/lib/xxhash.c")
+        .includes(&library.include_paths)
+        .out_dir(out.join("xxhash"));
+    if configure(&mut compiler)? {
+        println!("cargo:warning=liblz4 build options only apply to the vendored library");
+    }
+    compiler.define("XXH_NAMESPACE", SYSTEM_XXH_NAMESPACE);
+    compiler.compile("libxxhash.a");
+    println!("cargo:rustc-cfg=lz4_sys_system");
+
+    let include = env::join_paths(&library.include_paths)?;
+    println!("cargo:include={}", include.to_string_lossy());
+    generate_bindings(&library.include_paths)
+}
+
+/// Checks the version of the liblz4 headers and, unless cross-compiling, of the library itself
+/// by running a program printing `LZ4_versionNumber()`.
+fn check_system_version(library: &pkg_config::Library) -> Result<(), Box<dyn Error>> {
+    check_header_version(&library.include_paths, MIN_SYSTEM_VERSION_NUMBER)?;
+    if env::var("TARGET")? != env::var("HOST")? {
+        return Ok(());
+    }
+    let compiler = cc::Build::new()
+        .includes(&library.include_paths)
+        .cargo_metadata(false)
+        .try_get_compiler()?;
+    if compiler.is_like_msvc() {
+        return Ok(());
+    }
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").ok_or("missing OUT_DIR environment variable")?);
+    let source = out.join("lz4_version.c");
+    let program = out.join("lz4_version");
+    fs::write(
+        &source,
+        "#include <stdio.h>\n#include <lz4.h>\n\
+         int main(void) { printf(\"%d\\n\", LZ4_versionNumber()); return 0; }\n",
+    )?;
+    let mut command = compiler.to_command();
+    command.arg(&source).arg("-o").arg(&program);
+    for path in &library.link_paths {
+        command.arg(format!("-L{}", path.display()));
+    }
+    for lib in &library.libs {
+        command.arg(format!("-l{}", lib));
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "linking a program against liblz4 failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ).into());
+    }
+
+    let mut command = Command::new(&program);
+    if !library.link_paths.is_empty() {
+        let var = if cfg!(target_os = "macos") { "DYLD_LIBRARY_PATH" } else { "LD_LIBRARY_PATH" };
+        let mut paths = library.link_paths.clone();
+        if let Some(current) = env::var_os(var) {
+            paths.extend(env::split_paths(&current));
+        }
+        command.env(var, env::join_paths(paths)?);
+    }
+    let output = command.output()?;
+    let version: u32 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| "running a program printing the liblz4 version failed")?;
+    if version < MIN_SYSTEM_VERSION_NUMBER {
+        return Err(format!(
+            "liblz4 {} is older than {}, despite its pkg-config metadata",
+            version_string(version),
+            MIN_SYSTEM_VERSION
+        ).into());
+    }
+    Ok(())
+}
+
+/// Fails when the lz4.h found in the include paths is older than min_version.
+fn check_header_version<P: AsRef<Path>>(
+    include: &[P],
+    min_version: u32,
+) -> Result<(), Box<dyn Error>> {
+    let out = PathBuf::from(env::var_os("OUT_DIR").ok_or("missing OUT_DIR environment variable")?);
+    let source = out.join(format!("lz4_version_{}.c", min_version));
+    fs::write(
+        &source,
+        format!(
+            "#include <lz4.h>\n#if LZ4_VERSION_NUMBER < {}\n#error liblz4 too old\n#endif\n",
+            min_version
+        ),
+    )?;
+    let mut compiler = cc::Build::new();
+    compiler.file(&source).cargo_metadata(false).warnings(false);
+    for path in include {
+        compiler.include(path);
+    }
+    compiler
+        .out_dir(out.join("version"))
+        .try_compile("lz4_version")
+        .map_err(|_| format!("liblz4 headers are older than {}", version_string(min_version)))?;
+    Ok(())
+}
+
+fn version_string(version: u32) -> String {
+    format!("{}.{}.{}", version / 10000, version / 100 % 100, version % 100)
+}
+
+fn build_vendored() -> Result<(), Box<dyn Error>> {
+    if env::var_os("CARGO_FEATURE_EXPERIMENTAL").is_some() {
+        check_header_version(&["liblz4/lib"], MIN_EXPERIMENTAL_VERSION_NUMBER).map_err(|err| {
+            format!(
+                "the experimental feature (and the allocator feature of lz4) needs liblz4 {} \
+                 or newer in liblz4/lib: {}",
+                version_string(MIN_EXPERIMENTAL_VERSION_NUMBER),
+                err
+            )
+        })?;
+    }
     let mut compiler = cc::Build::new();
     compiler
         .file("liblz4/lib/lz4.c")
         .file("liblz4/lib/lz4frame.c")
         .file("liblz4/lib/lz4hc.c")
         .file("liblz4/lib/xxhash.c")
-        // We always compile the C with optimization, because otherwise it is 20x slower.
-        .opt_level(3);
+        .include("liblz4/lib");
+    configure(&mut compiler)?;
     match env::var("TARGET")
         .map_err(|err| format!("reading TARGET environment variable: {}", err))?
         .as_str()
@@ -31,6 +246,11 @@ fn run() -> Result<(), Box<dyn Error>> {
         compiler
             .flag("-fno-tree-vectorize");
       },
+      "wasm32-unknown-unknown" => {
+        // No C library, the headers liblz4 needs come from wasm-shim/ and src/wasm_shim.rs
+        compiler
+            .include("wasm-shim");
+      },
       _ => {}
     }
     compiler.compile("liblz4.a");
@@ -52,6 +272,98 @@ fn run() -> Result<(), Box<dyn Error>> {
         }
     }
     println!("cargo:root={}", dst.display());
+    println!("cargo:include={}", include.display());
+
+    generate_bindings(&[src])
+}
+
+/// Applies the build options from the features and environment variables. Returns whether any
+/// of them changes liblz4 itself, rather than only xxHash.
+fn configure(compiler: &mut cc::Build) -> Result<bool, Box<dyn Error>> {
+    let mut tuned = false;
+    // We compile the C with optimization by default, because otherwise it is 20x slower.
+    match env::var(OPT_LEVEL_VAR) {
+        Ok(level) => {
+            compiler.opt_level_str(&level);
+            tuned = true;
+        }
+        Err(_) => {
+            compiler.opt_level(3);
+        }
+    }
+    // Hash table size as a power of 2, 14 (16KB) by default
+    if let Some(value) = env_number(MEMORY_USAGE_VAR, 10, 20)? {
+        compiler.define("LZ4_MEMORY_USAGE", value.to_string().as_str());
+        tuned = true;
+    }
+    // Faster decoding loop, enabled by default on x86 and 64-bit ARM
+    if let Some(value) = env_number(FAST_DEC_LOOP_VAR, 0, 1)? {
+        compiler.define("LZ4_FAST_DEC_LOOP", value.to_string().as_str());
+        tuned = true;
+    }
+    // Allocate the block and frame states on the heap instead of the stack, HC already does
+    if env::var_os("CARGO_FEATURE_HEAPMODE").is_some() {
+        compiler.define("LZ4_HEAPMODE", "1");
+        compiler.define("LZ4F_HEAPMODE", "1");
+        tuned = true;
+    }
+    if env::var_os("CARGO_FEATURE_DISABLE_DEPRECATE_WARNINGS").is_some() {
+        compiler.define("LZ4_DISABLE_DEPRECATE_WARNINGS", None);
+    }
+    if env::var_os("CARGO_FEATURE_PREFIX_SYMBOLS").is_some() {
+        // Renames xxHash too, XXH_NAMESPACE would conflict with it
+        let header = env::current_dir()?.join("src").join("prefix.h");
+        let header = header.to_str().ok_or("non UTF-8 lz4-sys path")?;
+        if compiler.get_compiler().is_like_msvc() {
+            compiler.flag(format!("/FI{}", header));
+        } else {
+            compiler.flag("-include").flag(header);
+        }
+    } else if env::var_os("CARGO_FEATURE_XXH_NAMESPACE").is_some() {
+        compiler.define("XXH_NAMESPACE", XXH_NAMESPACE);
+    }
+    Ok(tuned)
+}
+
+fn env_number(var: &str, min: u32, max: u32) -> Result<Option<u32>, Box<dyn Error>> {
+    let value = match env::var(var) {
+        Ok(value) => value,
+        Err(_) => return Ok(None),
+    };
+    match value.trim().parse() {
+        Ok(number) if number >= min && number <= max => Ok(Some(number)),
+        _ => Err(format!("{} must be a number from {} to {}, got {:?}", var, min, max, value).into()),
+    }
+}
+
+/// Generates the liblz4 types and constants into `$OUT_DIR/bindings.rs`, from the headers the
+/// library is built with. They are only used by the tests checking the hand-written declarations,
+/// see src/layout.rs.
+#[cfg(feature = "bindgen")]
+fn generate_bindings<P: AsRef<Path>>(include: &[P]) -> Result<(), Box<dyn Error>> {
+    let out = PathBuf::from(env::var_os("OUT_DIR").ok_or("missing OUT_DIR environment variable")?);
+    let bindings = bindgen::Builder::default()
+        .header_contents(
+            "lz4-sys.h",
+            "#include <lz4.h>\n#include <lz4hc.h>\n#include <lz4frame.h>\n",
+        )
+        .clang_args(include.iter().map(|p| format!("-I{}", p.as_ref().display())))
+        .allowlist_type("LZ4F_.*")
+        .allowlist_var("LZ4F_.*|LZ4HC_CLEVEL_.*")
+        // One constant per enumerator, named as in C
+        .default_enum_style(bindgen::EnumVariation::Consts)
+        .prepend_enum_name(false)
+        .layout_tests(false)
+        .use_core()
+        .ctypes_prefix("libc")
+        .generate()
+        .map_err(|err| format!("generating bindings: {}", err))?;
+    bindings.write_to_file(out.join("bindings.rs"))
+        .map_err(|err| format!("writing bindings: {}", err))?;
+    Ok(())
+}
 
+#[cfg(not(feature = "bindgen"))]
+fn generate_bindings<P: AsRef<Path>>(_include: &[P]) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
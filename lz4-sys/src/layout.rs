@@ -0,0 +1,113 @@
+// Checks the hand-written declarations against the types bindgen generated from the liblz4
+// headers at build time. Only built with the `bindgen` feature.
+use core::mem::{align_of, size_of, zeroed};
+use super::*;
+
+#[allow(dead_code, non_camel_case_types, non_snake_case, non_upper_case_globals)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
+
+fn address<T>(value: &T) -> usize {
+    value as *const T as usize
+}
+
+macro_rules! assert_layout {
+    ($rust:ty, $c:ty) => {
+        assert_eq!(size_of::<$rust>(), size_of::<$c>(), "size of {}", stringify!($rust));
+        assert_eq!(align_of::<$rust>(), align_of::<$c>(), "alignment of {}", stringify!($rust));
+    };
+}
+
+// All the compared types are plain integers and enums with a zero variant, zeroed values are
+// valid for both sides
+macro_rules! assert_offset {
+    ($rust:ty, $rust_field:ident, $c:ty, $c_field:ident) => {
+        let rust: $rust = unsafe { zeroed() };
+        let c: $c = unsafe { zeroed() };
+        assert_eq!(
+            address(&rust.$rust_field) - address(&rust),
+            address(&c.$c_field) - address(&c),
+            "offset of {}::{}",
+            stringify!($rust),
+            stringify!($rust_field)
+        );
+        assert_eq!(
+            size_of_field(&rust.$rust_field),
+            size_of_field(&c.$c_field),
+            "size of {}::{}",
+            stringify!($rust),
+            stringify!($rust_field)
+        );
+    };
+}
+
+fn size_of_field<T>(_: &T) -> usize {
+    size_of::<T>()
+}
+
+#[test]
+fn test_frame_info_layout() {
+    use self::generated::LZ4F_frameInfo_t as C;
+    assert_layout!(LZ4FFrameInfo, C);
+    assert_offset!(LZ4FFrameInfo, block_size_id, C, blockSizeID);
+    assert_offset!(LZ4FFrameInfo, block_mode, C, blockMode);
+    assert_offset!(LZ4FFrameInfo, content_checksum_flag, C, contentChecksumFlag);
+    assert_offset!(LZ4FFrameInfo, frame_type, C, frameType);
+    assert_offset!(LZ4FFrameInfo, content_size, C, contentSize);
+    assert_offset!(LZ4FFrameInfo, dict_id, C, dictID);
+    assert_offset!(LZ4FFrameInfo, block_checksum_flag, C, blockChecksumFlag);
+}
+
+#[test]
+fn test_preferences_layout() {
+    use self::generated::LZ4F_preferences_t as C;
+    assert_layout!(LZ4FPreferences, C);
+    assert_offset!(LZ4FPreferences, frame_info, C, frameInfo);
+    assert_offset!(LZ4FPreferences, compression_level, C, compressionLevel);
+    assert_offset!(LZ4FPreferences, auto_flush, C, autoFlush);
+    assert_offset!(LZ4FPreferences, favor_dec_speed, C, favorDecSpeed);
+    assert_offset!(LZ4FPreferences, reserved, C, reserved);
+}
+
+#[test]
+fn test_options_layout() {
+    use self::generated::{LZ4F_compressOptions_t, LZ4F_decompressOptions_t};
+    assert_layout!(LZ4FCompressOptions, LZ4F_compressOptions_t);
+    assert_offset!(LZ4FCompressOptions, stable_src, LZ4F_compressOptions_t, stableSrc);
+    assert_offset!(LZ4FCompressOptions, reserved, LZ4F_compressOptions_t, reserved);
+    // The fields after stableDst were reserved before liblz4 v1.9.3, only the size is shared
+    assert_layout!(LZ4FDecompressOptions, LZ4F_decompressOptions_t);
+    assert_offset!(LZ4FDecompressOptions, stable_dst, LZ4F_decompressOptions_t, stableDst);
+}
+
+#[test]
+// The generated types depend on the target, the casts are not always no-ops
+#[allow(clippy::unnecessary_cast)]
+fn test_enum_values() {
+    use self::generated::*;
+    assert_eq!(BlockSize::Default as u32, LZ4F_default as u32);
+    assert_eq!(BlockSize::Max64KB as u32, LZ4F_max64KB as u32);
+    assert_eq!(BlockSize::Max256KB as u32, LZ4F_max256KB as u32);
+    assert_eq!(BlockSize::Max1MB as u32, LZ4F_max1MB as u32);
+    assert_eq!(BlockSize::Max4MB as u32, LZ4F_max4MB as u32);
+    assert_eq!(BlockMode::Linked as u32, LZ4F_blockLinked as u32);
+    assert_eq!(BlockMode::Independent as u32, LZ4F_blockIndependent as u32);
+    assert_eq!(ContentChecksum::NoChecksum as u32, LZ4F_noContentChecksum as u32);
+    assert_eq!(ContentChecksum::ChecksumEnabled as u32, LZ4F_contentChecksumEnabled as u32);
+    assert_eq!(FrameType::Frame as u32, LZ4F_frame as u32);
+    assert_eq!(FrameType::SkippableFrame as u32, LZ4F_skippableFrame as u32);
+    assert_eq!(BlockChecksum::NoBlockChecksum as u32, LZ4F_noBlockChecksum as u32);
+    assert_eq!(BlockChecksum::BlockChecksumEnabled as u32, LZ4F_blockChecksumEnabled as u32);
+}
+
+#[test]
+// The generated types depend on the target, the casts are not always no-ops
+#[allow(clippy::unnecessary_cast)]
+fn test_constants() {
+    assert_eq!(LZ4F_VERSION, generated::LZ4F_VERSION as c_uint);
+    assert_eq!(LZ4HC_CLEVEL_MIN, generated::LZ4HC_CLEVEL_MIN as c_int);
+    assert_eq!(LZ4HC_CLEVEL_DEFAULT, generated::LZ4HC_CLEVEL_DEFAULT as c_int);
+    assert_eq!(LZ4HC_CLEVEL_OPT_MIN, generated::LZ4HC_CLEVEL_OPT_MIN as c_int);
+    assert_eq!(LZ4HC_CLEVEL_MAX, generated::LZ4HC_CLEVEL_MAX as c_int);
+}
@@ -1,8 +1,45 @@
 #![no_std]
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 extern crate libc;
+#[cfg(any(all(target_arch = "wasm32", target_os = "unknown"), test))]
+extern crate alloc;
 
 use libc::{c_void, c_char, c_uint, size_t, c_int};
 
+// wasm32-unknown-unknown has no C library: the C types are defined here, and the allocator the
+// vendored liblz4 is built against is implemented in Rust.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[allow(non_camel_case_types)]
+mod libc {
+    pub use core::ffi::c_void;
+    pub type c_char = i8;
+    pub type c_int = i32;
+    pub type c_uint = u32;
+    pub type size_t = usize;
+}
+
+#[cfg(any(all(target_arch = "wasm32", target_os = "unknown"), test))]
+mod wasm_shim;
+
+#[cfg(all(test, feature = "bindgen"))]
+mod layout;
+
+// Declares liblz4 functions, linked to their lz4_sys_ prefixed names when the prefix-symbols
+// feature renames the bundled library (see src/prefix.h).
+macro_rules! lz4_extern {
+    ($(
+        $(#[$attr:meta])*
+        pub fn $name:ident($($arg:ident: $ty:ty),* $(,)*) $(-> $ret:ty)*;
+    )*) => {
+        extern "C" {$(
+            $(#[$attr])*
+            #[cfg_attr(feature = "prefix-symbols",
+                       link_name = concat!("lz4_sys_", stringify!($name)))]
+            pub fn $name($($arg: $ty),*) $(-> $ret)*;
+        )*}
+    };
+}
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub struct LZ4FCompressionContext(pub *mut c_void);
@@ -15,7 +52,7 @@ unsafe impl Send for LZ4FDecompressionContext {}
 
 pub type LZ4FErrorCode = size_t;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(u32)]
 pub enum BlockSize {
     Default = 0, // Default - 64KB
@@ -37,34 +74,55 @@ impl BlockSize {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(u32)]
 pub enum BlockMode {
     Linked = 0,
     Independent,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 #[repr(u32)]
 pub enum ContentChecksum {
     NoChecksum = 0,
     ChecksumEnabled,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+#[repr(u32)]
+pub enum FrameType {
+    Frame = 0,
+    SkippableFrame,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[repr(u32)]
+pub enum BlockChecksum {
+    NoBlockChecksum = 0,
+    BlockChecksumEnabled,
+}
+
 #[repr(C)]
 pub struct LZ4FFrameInfo {
     pub block_size_id: BlockSize,
     pub block_mode: BlockMode,
     pub content_checksum_flag: ContentChecksum,
-    pub reserved: [c_uint; 5],
+    pub frame_type: FrameType,
+    pub content_size: u64, // 0 == unknown
+    pub dict_id: c_uint, // 0 == no dictionary
+    pub block_checksum_flag: BlockChecksum,
 }
 
 #[repr(C)]
 pub struct LZ4FPreferences {
     pub frame_info: LZ4FFrameInfo,
-    pub compression_level: c_uint, // 0 == default (fast mode); values above 16 count as 16
+    pub compression_level: c_int, /* 0 == default (fast mode); values above LZ4HC_CLEVEL_MAX
+                                   * count as LZ4HC_CLEVEL_MAX; values below 0 trigger
+                                   * "fast acceleration" */
     pub auto_flush: c_uint, // 1 == always flush : reduce need for tmp buffer
-    pub reserved: [c_uint; 4],
+    pub favor_dec_speed: c_uint, /* 1 == parser favors decompression speed over compression
+                                  * ratio; only works for levels >= LZ4HC_CLEVEL_OPT_MIN */
+    pub reserved: [c_uint; 3],
 }
 
 #[repr(C)]
@@ -79,7 +137,9 @@ pub struct LZ4FCompressOptions {
 pub struct LZ4FDecompressOptions {
     pub stable_dst: c_uint, /* guarantee that decompressed data will still be there on next
                              * function calls (avoid storage into tmp buffers) */
-    pub reserved: [c_uint; 3],
+    pub skip_checksums: c_uint, /* disable checksum calculation and verification, even when
+                                 * one is present in frame; honoured since liblz4 v1.9.3 */
+    pub reserved: [c_uint; 2],
 }
 
 #[repr(C)]
@@ -88,9 +148,53 @@ pub struct LZ4StreamEncode(c_void);
 #[repr(C)]
 pub struct LZ4StreamDecode(c_void);
 
+#[repr(C)]
+pub struct LZ4StreamHC(c_void);
+
+#[repr(C)]
+pub struct LZ4FCDict(c_void);
+
+#[repr(C)]
+pub struct XXH32State(c_void);
+
+// void* (*LZ4F_AllocFunction) (void* opaqueState, size_t size)
+#[cfg(feature = "experimental")]
+pub type LZ4FAllocFunction = Option<unsafe extern "C" fn(opaque_state: *mut c_void,
+                                                         size: size_t)
+                                                         -> *mut c_void>;
+
+// void* (*LZ4F_CallocFunction) (void* opaqueState, size_t size)
+#[cfg(feature = "experimental")]
+pub type LZ4FCallocFunction = Option<unsafe extern "C" fn(opaque_state: *mut c_void,
+                                                          size: size_t)
+                                                          -> *mut c_void>;
+
+// void (*LZ4F_FreeFunction) (void* opaqueState, void* address)
+#[cfg(feature = "experimental")]
+pub type LZ4FFreeFunction = Option<unsafe extern "C" fn(opaque_state: *mut c_void,
+                                                        address: *mut c_void)>;
+
+/// Allocation functions of the contexts created by the `LZ4F_create*_advanced()` functions. A
+/// missing function falls back to the C library, a missing `custom_calloc` to `custom_alloc`
+/// followed by `memset`.
+#[cfg(feature = "experimental")]
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct LZ4FCustomMem {
+    pub custom_alloc: LZ4FAllocFunction,
+    pub custom_calloc: LZ4FCallocFunction,
+    pub custom_free: LZ4FFreeFunction,
+    pub opaque_state: *mut c_void,
+}
+
 pub const LZ4F_VERSION: c_uint = 100;
 
-extern "C" {
+pub const LZ4HC_CLEVEL_MIN: c_int = 3;
+pub const LZ4HC_CLEVEL_DEFAULT: c_int = 9;
+pub const LZ4HC_CLEVEL_OPT_MIN: c_int = 10;
+pub const LZ4HC_CLEVEL_MAX: c_int = 12;
+
+lz4_extern! {
 
     // int LZ4_compress_default(const char* source, char* dest, int sourceSize, int maxDestSize);
     #[allow(non_snake_case)]
@@ -114,6 +218,12 @@ extern "C" {
     // const char* LZ4F_getErrorName(LZ4F_errorCode_t code);
     pub fn LZ4F_getErrorName(code: size_t) -> *const c_char;
 
+    // unsigned LZ4F_getVersion(void);
+    pub fn LZ4F_getVersion() -> c_uint;
+
+    // int LZ4F_compressionLevel_max(void);
+    pub fn LZ4F_compressionLevel_max() -> c_int;
+
     // LZ4F_createCompressionContext() :
     // The first thing to do is to create a compressionContext object, which will be used in all
     // compression operations.
@@ -155,6 +265,38 @@ extern "C" {
                               preferencesPtr: *const LZ4FPreferences)
                               -> LZ4FErrorCode;
 
+    // LZ4F_compressFrameBound() :
+    // Returns the maximum possible compressed size with LZ4F_compressFrame() given srcSize and
+    // preferences.
+    // preferencesPtr is optional. It can be replaced by NULL, in which case, the function will
+    // assume default preferences.
+    // Note : this result is only usable with LZ4F_compressFrame().
+    //        It may also be used with LZ4F_compressUpdate() _if no flush() operation_ is
+    //        performed.
+    //
+    // size_t LZ4F_compressFrameBound(size_t srcSize, const LZ4F_preferences_t* preferencesPtr);
+    pub fn LZ4F_compressFrameBound(srcSize: size_t,
+                                   preferencesPtr: *const LZ4FPreferences)
+                                   -> LZ4FErrorCode;
+
+    // LZ4F_compressFrame() :
+    // Compress an entire srcBuffer into a valid LZ4 frame.
+    // dstCapacity MUST be >= LZ4F_compressFrameBound(srcSize, preferencesPtr).
+    // The LZ4F_preferences_t structure is optional : you can provide NULL as argument. All
+    // preferences will be set to default.
+    // The result of the function is the number of bytes written into dstBuffer
+    // or an error code if it fails (can be tested using LZ4F_isError())
+    //
+    // size_t LZ4F_compressFrame(void* dstBuffer, size_t dstCapacity,
+    //                           const void* srcBuffer, size_t srcSize,
+    //                           const LZ4F_preferences_t* preferencesPtr);
+    pub fn LZ4F_compressFrame(dstBuffer: *mut u8,
+                              dstCapacity: size_t,
+                              srcBuffer: *const u8,
+                              srcSize: size_t,
+                              preferencesPtr: *const LZ4FPreferences)
+                              -> LZ4FErrorCode;
+
     // LZ4F_compressBound() :
     // Provides the minimum size of Dst buffer given srcSize to handle worst case situations.
     // preferencesPtr is optional : you can provide NULL as argument, all preferences will then
@@ -251,6 +393,15 @@ extern "C" {
     // LZ4F_errorCode_t LZ4F_freeDecompressionContext(LZ4F_decompressionContext_t ctx);
     pub fn LZ4F_freeDecompressionContext(ctx: LZ4FDecompressionContext) -> LZ4FErrorCode;
 
+    // LZ4F_resetDecompressionContext() :
+    // In case of an error, the context is left in "undefined" state.
+    // In which case, it's necessary to reset it, before re-using it.
+    // This method can also be used to abruptly stop any unfinished decompression,
+    // and start a new one using same context resources.
+    //
+    // void LZ4F_resetDecompressionContext(LZ4F_dctx* dctx);   /* always successful */
+    pub fn LZ4F_resetDecompressionContext(ctx: LZ4FDecompressionContext);
+
     // LZ4F_getFrameInfo()
     // This function decodes frame header information, such as blockSize.
     // It is optional : you could start by calling directly LZ4F_decompress() instead.
@@ -273,6 +424,11 @@ extern "C" {
                              srcSizePtr: &mut size_t)
                              -> LZ4FErrorCode;
 
+    // size_t LZ4F_headerSize(const void* src, size_t srcSize)
+    // Since liblz4 v1.9.0. Returns the size of the frame header starting at src, which must hold
+    // at least the first 5 bytes of the frame, or an error code.
+    pub fn LZ4F_headerSize(src: *const u8, src_size: size_t) -> LZ4FErrorCode;
+
     // LZ4F_decompress()
     // Call this function repetitively to regenerate data compressed within srcBuffer.
     // The function will attempt to decode *srcSizePtr bytes from srcBuffer, into dstBuffer of
@@ -357,9 +513,441 @@ extern "C" {
     // int LZ4_freeStreamDecode(LZ4_streamDecode_t* LZ4_stream)
     pub fn LZ4_freeStreamDecode(LZ4_stream: *mut LZ4StreamDecode) -> c_int;
 
+    // const char* LZ4_versionString(void)
+    pub fn LZ4_versionString() -> *const c_char;
+
+    // int LZ4_sizeofState(void)
+    pub fn LZ4_sizeofState() -> c_int;
+
+    // LZ4_stream_t* LZ4_initStream(void* buffer, size_t size)
+    // Since liblz4 v1.9.0. Returns NULL when the buffer is too small or misaligned.
+    pub fn LZ4_initStream(buffer: *mut c_void, size: size_t) -> *mut LZ4StreamEncode;
+
+    // int LZ4_compress_fast_extState(void* state, const char* src, char* dst, int srcSize,
+    //                                int dstCapacity, int acceleration)
+    pub fn LZ4_compress_fast_extState(state: *mut c_void,
+                                      src: *const c_char,
+                                      dst: *mut c_char,
+                                      src_size: c_int,
+                                      dst_capacity: c_int,
+                                      acceleration: c_int)
+                                      -> c_int;
+
+    // int LZ4_compress_destSize(const char* src, char* dst, int* srcSizePtr, int targetDstSize)
+    pub fn LZ4_compress_destSize(src: *const c_char,
+                                 dst: *mut c_char,
+                                 src_size_ptr: *mut c_int,
+                                 target_dst_size: c_int)
+                                 -> c_int;
+
+    // int LZ4_decompress_fast(const char* src, char* dst, int originalSize)
+    // Not protected against malformed input, prefer LZ4_decompress_safe().
+    pub fn LZ4_decompress_fast(src: *const c_char, dst: *mut c_char, original_size: c_int) -> c_int;
+
+    // int LZ4_decompress_safe_partial(const char* src, char* dst, int srcSize,
+    //                                 int targetOutputSize, int dstCapacity)
+    pub fn LZ4_decompress_safe_partial(src: *const c_char,
+                                       dst: *mut c_char,
+                                       src_size: c_int,
+                                       target_output_size: c_int,
+                                       dst_capacity: c_int)
+                                       -> c_int;
+
+    // void LZ4_resetStream(LZ4_stream_t* streamPtr)
+    pub fn LZ4_resetStream(stream: *mut LZ4StreamEncode);
+
+    // int LZ4_loadDict(LZ4_stream_t* streamPtr, const char* dictionary, int dictSize)
+    pub fn LZ4_loadDict(stream: *mut LZ4StreamEncode,
+                        dictionary: *const c_char,
+                        dict_size: c_int)
+                        -> c_int;
+
+    // int LZ4_compress_fast_continue(LZ4_stream_t* streamPtr, const char* src, char* dst,
+    //                                int srcSize, int dstCapacity, int acceleration)
+    pub fn LZ4_compress_fast_continue(stream: *mut LZ4StreamEncode,
+                                      src: *const c_char,
+                                      dst: *mut c_char,
+                                      src_size: c_int,
+                                      dst_capacity: c_int,
+                                      acceleration: c_int)
+                                      -> c_int;
+
+    // int LZ4_saveDict(LZ4_stream_t* streamPtr, char* safeBuffer, int maxDictSize)
+    pub fn LZ4_saveDict(stream: *mut LZ4StreamEncode,
+                        safe_buffer: *mut c_char,
+                        max_dict_size: c_int)
+                        -> c_int;
+
+    // int LZ4_setStreamDecode(LZ4_streamDecode_t* LZ4_streamDecode, const char* dictionary,
+    //                         int dictSize)
+    pub fn LZ4_setStreamDecode(stream: *mut LZ4StreamDecode,
+                               dictionary: *const c_char,
+                               dict_size: c_int)
+                               -> c_int;
+
+    // int LZ4_decoderRingBufferSize(int maxBlockSize)
+    pub fn LZ4_decoderRingBufferSize(max_block_size: c_int) -> c_int;
+
+    // int LZ4_decompress_fast_continue(LZ4_streamDecode_t* LZ4_streamDecode, const char* src,
+    //                                  char* dst, int originalSize)
+    pub fn LZ4_decompress_fast_continue(stream: *mut LZ4StreamDecode,
+                                        src: *const c_char,
+                                        dst: *mut c_char,
+                                        original_size: c_int)
+                                        -> c_int;
+
+    // int LZ4_decompress_safe_usingDict(const char* src, char* dst, int srcSize, int dstCapacity,
+    //                                   const char* dictStart, int dictSize)
+    pub fn LZ4_decompress_safe_usingDict(src: *const c_char,
+                                         dst: *mut c_char,
+                                         src_size: c_int,
+                                         dst_capacity: c_int,
+                                         dict_start: *const c_char,
+                                         dict_size: c_int)
+                                         -> c_int;
+
+    // int LZ4_decompress_fast_usingDict(const char* src, char* dst, int originalSize,
+    //                                   const char* dictStart, int dictSize)
+    pub fn LZ4_decompress_fast_usingDict(src: *const c_char,
+                                         dst: *mut c_char,
+                                         original_size: c_int,
+                                         dict_start: *const c_char,
+                                         dict_size: c_int)
+                                         -> c_int;
+
+    // int LZ4_sizeofStateHC(void)
+    pub fn LZ4_sizeofStateHC() -> c_int;
+
+    // LZ4_streamHC_t* LZ4_initStreamHC(void* buffer, size_t size)
+    // Since liblz4 v1.9.0. Returns NULL when the buffer is too small or misaligned.
+    pub fn LZ4_initStreamHC(buffer: *mut c_void, size: size_t) -> *mut LZ4StreamHC;
+
+    // int LZ4_compress_HC_extStateHC(void* state, const char* src, char* dst, int srcSize,
+    //                                int maxDstSize, int compressionLevel)
+    pub fn LZ4_compress_HC_extStateHC(state: *mut c_void,
+                                      src: *const c_char,
+                                      dst: *mut c_char,
+                                      src_size: c_int,
+                                      max_dst_size: c_int,
+                                      compression_level: c_int)
+                                      -> c_int;
+
+    // LZ4_streamHC_t* LZ4_createStreamHC(void)
+    pub fn LZ4_createStreamHC() -> *mut LZ4StreamHC;
+
+    // int LZ4_freeStreamHC(LZ4_streamHC_t* streamHCPtr)
+    pub fn LZ4_freeStreamHC(stream: *mut LZ4StreamHC) -> c_int;
+
+    // void LZ4_resetStreamHC(LZ4_streamHC_t* streamHCPtr, int compressionLevel)
+    pub fn LZ4_resetStreamHC(stream: *mut LZ4StreamHC, compression_level: c_int);
+
+    // int LZ4_loadDictHC(LZ4_streamHC_t* streamHCPtr, const char* dictionary, int dictSize)
+    pub fn LZ4_loadDictHC(stream: *mut LZ4StreamHC,
+                          dictionary: *const c_char,
+                          dict_size: c_int)
+                          -> c_int;
+
+    // int LZ4_compress_HC_continue(LZ4_streamHC_t* streamHCPtr, const char* src, char* dst,
+    //                              int srcSize, int maxDstSize)
+    pub fn LZ4_compress_HC_continue(stream: *mut LZ4StreamHC,
+                                    src: *const c_char,
+                                    dst: *mut c_char,
+                                    src_size: c_int,
+                                    max_dst_size: c_int)
+                                    -> c_int;
+
+    // int LZ4_saveDictHC(LZ4_streamHC_t* streamHCPtr, char* safeBuffer, int maxDictSize)
+    pub fn LZ4_saveDictHC(stream: *mut LZ4StreamHC,
+                          safe_buffer: *mut c_char,
+                          max_dict_size: c_int)
+                          -> c_int;
+
+    // XXH32_hash_t XXH32(const void* input, size_t length, unsigned int seed)
+    // Private copy built next to a system liblz4, see build.rs
+    #[cfg_attr(lz4_sys_system, link_name = "lz4_sys_XXH32")]
+    #[cfg_attr(all(feature = "xxh-namespace", not(feature = "prefix-symbols"),
+                   not(lz4_sys_system)),
+               link_name = "LZ4_XXH32")]
+    pub fn XXH32(input: *const u8, length: size_t, seed: c_uint) -> c_uint;
+
+    // XXH32_state_t* XXH32_createState(void)
+    #[cfg_attr(lz4_sys_system, link_name = "lz4_sys_XXH32_createState")]
+    #[cfg_attr(all(feature = "xxh-namespace", not(feature = "prefix-symbols"),
+                   not(lz4_sys_system)),
+               link_name = "LZ4_XXH32_createState")]
+    pub fn XXH32_createState() -> *mut XXH32State;
+
+    // XXH_errorcode XXH32_freeState(XXH32_state_t* statePtr)
+    #[cfg_attr(lz4_sys_system, link_name = "lz4_sys_XXH32_freeState")]
+    #[cfg_attr(all(feature = "xxh-namespace", not(feature = "prefix-symbols"),
+                   not(lz4_sys_system)),
+               link_name = "LZ4_XXH32_freeState")]
+    pub fn XXH32_freeState(statePtr: *mut XXH32State) -> c_int;
+
+    // XXH_errorcode XXH32_reset(XXH32_state_t* statePtr, unsigned int seed)
+    #[cfg_attr(lz4_sys_system, link_name = "lz4_sys_XXH32_reset")]
+    #[cfg_attr(all(feature = "xxh-namespace", not(feature = "prefix-symbols"),
+                   not(lz4_sys_system)),
+               link_name = "LZ4_XXH32_reset")]
+    pub fn XXH32_reset(statePtr: *mut XXH32State, seed: c_uint) -> c_int;
+
+    // XXH_errorcode XXH32_update(XXH32_state_t* statePtr, const void* input, size_t length)
+    #[cfg_attr(lz4_sys_system, link_name = "lz4_sys_XXH32_update")]
+    #[cfg_attr(all(feature = "xxh-namespace", not(feature = "prefix-symbols"),
+                   not(lz4_sys_system)),
+               link_name = "LZ4_XXH32_update")]
+    pub fn XXH32_update(statePtr: *mut XXH32State, input: *const u8, length: size_t) -> c_int;
+
+    // XXH32_hash_t XXH32_digest(const XXH32_state_t* statePtr)
+    #[cfg_attr(lz4_sys_system, link_name = "lz4_sys_XXH32_digest")]
+    #[cfg_attr(all(feature = "xxh-namespace", not(feature = "prefix-symbols"),
+                   not(lz4_sys_system)),
+               link_name = "LZ4_XXH32_digest")]
+    pub fn XXH32_digest(statePtr: *const XXH32State) -> c_uint;
+
+}
+
+// Static linking only APIs (LZ4_STATIC_LINKING_ONLY, LZ4_HC_STATIC_LINKING_ONLY and
+// LZ4F_STATIC_LINKING_ONLY). They may change between liblz4 versions and are not exported by
+// shared builds, so they are only available with the vendored sources.
+#[cfg(feature = "experimental")]
+lz4_extern! {
+
+    // void LZ4_resetStream_fast(LZ4_stream_t* streamPtr)
+    pub fn LZ4_resetStream_fast(stream: *mut LZ4StreamEncode);
+
+    // int LZ4_compress_fast_extState_fastReset(void* state, const char* src, char* dst,
+    //                                          int srcSize, int dstCapacity, int acceleration)
+    pub fn LZ4_compress_fast_extState_fastReset(state: *mut c_void,
+                                                src: *const c_char,
+                                                dst: *mut c_char,
+                                                src_size: c_int,
+                                                dst_capacity: c_int,
+                                                acceleration: c_int)
+                                                -> c_int;
+
+    // void LZ4_attach_dictionary(LZ4_stream_t* working_stream,
+    //                            const LZ4_stream_t* dictionary_stream)
+    pub fn LZ4_attach_dictionary(working_stream: *mut LZ4StreamEncode,
+                                 dictionary_stream: *const LZ4StreamEncode);
+
+    // int LZ4_compress_HC_destSize(void* LZ4HC_Data, const char* src, char* dst,
+    //                              int* srcSizePtr, int targetDstSize, int compressionLevel)
+    pub fn LZ4_compress_HC_destSize(state: *mut c_void,
+                                    src: *const c_char,
+                                    dst: *mut c_char,
+                                    src_size_ptr: *mut c_int,
+                                    target_dst_size: c_int,
+                                    compression_level: c_int)
+                                    -> c_int;
+
+    // int LZ4_compress_HC_continue_destSize(LZ4_streamHC_t* LZ4_streamHCPtr, const char* src,
+    //                                       char* dst, int* srcSizePtr, int targetDstSize)
+    pub fn LZ4_compress_HC_continue_destSize(stream: *mut LZ4StreamHC,
+                                             src: *const c_char,
+                                             dst: *mut c_char,
+                                             src_size_ptr: *mut c_int,
+                                             target_dst_size: c_int)
+                                             -> c_int;
+
+    // void LZ4_setCompressionLevel(LZ4_streamHC_t* LZ4_streamHCPtr, int compressionLevel)
+    pub fn LZ4_setCompressionLevel(stream: *mut LZ4StreamHC, compression_level: c_int);
+
+    // void LZ4_favorDecompressionSpeed(LZ4_streamHC_t* LZ4_streamHCPtr, int favor)
+    pub fn LZ4_favorDecompressionSpeed(stream: *mut LZ4StreamHC, favor: c_int);
+
+    // void LZ4_resetStreamHC_fast(LZ4_streamHC_t* LZ4_streamHCPtr, int compressionLevel)
+    pub fn LZ4_resetStreamHC_fast(stream: *mut LZ4StreamHC, compression_level: c_int);
+
+    // int LZ4_compress_HC_extStateHC_fastReset(void* state, const char* src, char* dst,
+    //                                          int srcSize, int dstCapacity,
+    //                                          int compressionLevel)
+    pub fn LZ4_compress_HC_extStateHC_fastReset(state: *mut c_void,
+                                                src: *const c_char,
+                                                dst: *mut c_char,
+                                                src_size: c_int,
+                                                dst_capacity: c_int,
+                                                compression_level: c_int)
+                                                -> c_int;
+
+    // void LZ4_attach_HC_dictionary(LZ4_streamHC_t* working_stream,
+    //                               const LZ4_streamHC_t* dictionary_stream)
+    pub fn LZ4_attach_HC_dictionary(working_stream: *mut LZ4StreamHC,
+                                    dictionary_stream: *const LZ4StreamHC);
+
+    // LZ4F_errorCodes LZ4F_getErrorCode(size_t functionResult)
+    // Returns the LZ4F_errorCodes value of a failed call, 0 when there was no error.
+    pub fn LZ4F_getErrorCode(function_result: size_t) -> c_int;
+
+    // size_t LZ4F_getBlockSize(LZ4F_blockSizeID_t blockSizeID)
+    // Returns the maximum size of a block, or an error code for an invalid block size id.
+    pub fn LZ4F_getBlockSize(block_size_id: BlockSize) -> size_t;
+
+    // LZ4F_CDict* LZ4F_createCDict(const void* dictBuffer, size_t dictSize)
+    // The dictionary is copied, the buffer can be released afterwards.
+    pub fn LZ4F_createCDict(dict_buffer: *const u8, dict_size: size_t) -> *mut LZ4FCDict;
+
+    // void LZ4F_freeCDict(LZ4F_CDict* CDict)
+    pub fn LZ4F_freeCDict(cdict: *mut LZ4FCDict);
+
+    // LZ4F_cctx* LZ4F_createCompressionContext_advanced(LZ4F_CustomMem customMem,
+    //                                                  unsigned version)
+    // Returns NULL on failure. The context is released by LZ4F_freeCompressionContext().
+    pub fn LZ4F_createCompressionContext_advanced(custom_mem: LZ4FCustomMem,
+                                                  version: c_uint)
+                                                  -> *mut c_void;
+
+    // LZ4F_dctx* LZ4F_createDecompressionContext_advanced(LZ4F_CustomMem customMem,
+    //                                                    unsigned version)
+    // Returns NULL on failure. The context is released by LZ4F_freeDecompressionContext().
+    pub fn LZ4F_createDecompressionContext_advanced(custom_mem: LZ4FCustomMem,
+                                                    version: c_uint)
+                                                    -> *mut c_void;
+
+    // LZ4F_CDict* LZ4F_createCDict_advanced(LZ4F_CustomMem customMem, const void* dictBuffer,
+    //                                       size_t dictSize)
+    pub fn LZ4F_createCDict_advanced(custom_mem: LZ4FCustomMem,
+                                     dict_buffer: *const u8,
+                                     dict_size: size_t)
+                                     -> *mut LZ4FCDict;
+
+    // size_t LZ4F_compressFrame_usingCDict(LZ4F_cctx* cctx, void* dst, size_t dstCapacity,
+    //                                      const void* src, size_t srcSize,
+    //                                      const LZ4F_CDict* cdict,
+    //                                      const LZ4F_preferences_t* preferencesPtr)
+    pub fn LZ4F_compressFrame_usingCDict(ctx: LZ4FCompressionContext,
+                                         dst: *mut u8,
+                                         dst_capacity: size_t,
+                                         src: *const u8,
+                                         src_size: size_t,
+                                         cdict: *const LZ4FCDict,
+                                         preferences: *const LZ4FPreferences)
+                                         -> LZ4FErrorCode;
+
+    // size_t LZ4F_compressBegin_usingCDict(LZ4F_cctx* cctx, void* dstBuffer,
+    //                                      size_t dstCapacity, const LZ4F_CDict* cdict,
+    //                                      const LZ4F_preferences_t* prefsPtr)
+    pub fn LZ4F_compressBegin_usingCDict(ctx: LZ4FCompressionContext,
+                                         dst_buffer: *mut u8,
+                                         dst_capacity: size_t,
+                                         cdict: *const LZ4FCDict,
+                                         preferences: *const LZ4FPreferences)
+                                         -> LZ4FErrorCode;
+
+    // size_t LZ4F_decompress_usingDict(LZ4F_dctx* dctxPtr, void* dstBuffer, size_t* dstSizePtr,
+    //                                  const void* srcBuffer, size_t* srcSizePtr,
+    //                                  const void* dict, size_t dictSize,
+    //                                  const LZ4F_decompressOptions_t* decompressOptionsPtr)
+    pub fn LZ4F_decompress_usingDict(ctx: LZ4FDecompressionContext,
+                                     dst_buffer: *mut u8,
+                                     dst_size_ptr: &mut size_t,
+                                     src_buffer: *const u8,
+                                     src_size_ptr: &mut size_t,
+                                     dict: *const u8,
+                                     dict_size: size_t,
+                                     options: *const LZ4FDecompressOptions)
+                                     -> LZ4FErrorCode;
 }
 
-#[test]
-fn test_version_number() {
-    unsafe { LZ4_versionNumber(); }
+#[cfg(test)]
+mod test {
+    use libc::size_t;
+    use super::*;
+
+    #[test]
+    fn test_version_number() {
+        unsafe { LZ4_versionNumber(); }
+    }
+
+    #[test]
+    fn test_header_size() {
+        // Magic number, FLG, BD and header checksum of a frame without optional fields
+        let header = b"\x04\x22\x4d\x18\x40\x40\xc0";
+        unsafe {
+            assert_eq!(LZ4F_headerSize(header.as_ptr(), header.len()), 7);
+            assert_eq!(LZ4F_isError(LZ4F_headerSize(header.as_ptr(), 4)), 1);
+        }
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn test_block_size() {
+        unsafe {
+            assert_eq!(LZ4F_getBlockSize(BlockSize::Max64KB), 64 * 1024);
+            assert_eq!(LZ4F_getBlockSize(BlockSize::Max4MB), 4 * 1024 * 1024);
+        }
+    }
+
+    #[test]
+    fn test_frame_version() {
+        unsafe {
+            assert_eq!(LZ4F_getVersion(), LZ4F_VERSION);
+            assert_eq!(LZ4F_compressionLevel_max(), LZ4HC_CLEVEL_MAX);
+        }
+    }
+
+    #[test]
+    fn test_init_stream() {
+        unsafe {
+            for &(size, hc) in [(LZ4_sizeofState(), false), (LZ4_sizeofStateHC(), true)].iter() {
+                let size = size as usize;
+                let mut state = alloc::vec::Vec::new();
+                state.resize(size / 8 + 1, 0u64);
+                let buffer = state.as_mut_ptr() as *mut u8;
+                let init = |offset: usize, size: usize| if hc {
+                    LZ4_initStreamHC(buffer.add(offset) as *mut c_void, size).is_null()
+                } else {
+                    LZ4_initStream(buffer.add(offset) as *mut c_void, size).is_null()
+                };
+                assert!(!init(0, size));
+                assert!(init(0, size - 1));
+                assert!(init(1, size));
+            }
+        }
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn test_cdict() {
+        let dict = [0x42u8; 1024];
+        unsafe {
+            let cdict = LZ4F_createCDict(dict.as_ptr(), dict.len());
+            assert!(!cdict.is_null());
+            LZ4F_freeCDict(cdict);
+            assert_eq!(LZ4F_getErrorCode(0), 0);
+        }
+    }
+
+    #[cfg(feature = "experimental")]
+    unsafe extern "C" fn counting_alloc(state: *mut c_void, size: size_t) -> *mut c_void {
+        *(state as *mut usize) += 1;
+        ::libc::malloc(size)
+    }
+
+    #[cfg(feature = "experimental")]
+    unsafe extern "C" fn counting_free(state: *mut c_void, address: *mut c_void) {
+        if !address.is_null() {
+            *(state as *mut usize) -= 1;
+        }
+        ::libc::free(address)
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn test_custom_mem() {
+        let mut live = 0usize;
+        let custom_mem = LZ4FCustomMem {
+            custom_alloc: Some(counting_alloc),
+            custom_calloc: None,
+            custom_free: Some(counting_free),
+            opaque_state: &mut live as *mut usize as *mut c_void,
+        };
+        unsafe {
+            let ctx = LZ4F_createDecompressionContext_advanced(custom_mem, LZ4F_VERSION);
+            assert!(!ctx.is_null());
+            assert_eq!(live, 1);
+            LZ4F_freeDecompressionContext(LZ4FDecompressionContext(ctx));
+            assert_eq!(live, 0);
+        }
+    }
 }
@@ -0,0 +1,152 @@
+/* Renames the external symbols of the bundled liblz4 and xxHash with the lz4_sys_ prefix, so they
+ * can't clash with another copy of liblz4 linked into the same binary. build.rs includes it ahead
+ * of every source file when the prefix-symbols feature is enabled, and src/lib.rs links to the
+ * prefixed names.
+ *
+ * The list was taken from liblz4 v1.9.2, v1.9.4 and v1.10.0, names missing from the bundled
+ * version are harmless. After upgrading liblz4, check that `nm -g --defined-only liblz4.a` only
+ * lists lz4_sys_ symbols. */
+#ifndef LZ4_SYS_PREFIX_H
+#define LZ4_SYS_PREFIX_H
+
+#define LZ4F_compressBegin lz4_sys_LZ4F_compressBegin
+#define LZ4F_compressBegin_internal lz4_sys_LZ4F_compressBegin_internal
+#define LZ4F_compressBegin_usingCDict lz4_sys_LZ4F_compressBegin_usingCDict
+#define LZ4F_compressBegin_usingDict lz4_sys_LZ4F_compressBegin_usingDict
+#define LZ4F_compressBegin_usingDictOnce lz4_sys_LZ4F_compressBegin_usingDictOnce
+#define LZ4F_compressBound lz4_sys_LZ4F_compressBound
+#define LZ4F_compressEnd lz4_sys_LZ4F_compressEnd
+#define LZ4F_compressFrame lz4_sys_LZ4F_compressFrame
+#define LZ4F_compressFrameBound lz4_sys_LZ4F_compressFrameBound
+#define LZ4F_compressFrame_usingCDict lz4_sys_LZ4F_compressFrame_usingCDict
+#define LZ4F_compressUpdate lz4_sys_LZ4F_compressUpdate
+#define LZ4F_compressionLevel_max lz4_sys_LZ4F_compressionLevel_max
+#define LZ4F_createCDict lz4_sys_LZ4F_createCDict
+#define LZ4F_createCDict_advanced lz4_sys_LZ4F_createCDict_advanced
+#define LZ4F_createCompressionContext lz4_sys_LZ4F_createCompressionContext
+#define LZ4F_createCompressionContext_advanced lz4_sys_LZ4F_createCompressionContext_advanced
+#define LZ4F_createDecompressionContext lz4_sys_LZ4F_createDecompressionContext
+#define LZ4F_createDecompressionContext_advanced lz4_sys_LZ4F_createDecompressionContext_advanced
+#define LZ4F_decompress lz4_sys_LZ4F_decompress
+#define LZ4F_decompress_usingDict lz4_sys_LZ4F_decompress_usingDict
+#define LZ4F_flush lz4_sys_LZ4F_flush
+#define LZ4F_freeCDict lz4_sys_LZ4F_freeCDict
+#define LZ4F_freeCompressionContext lz4_sys_LZ4F_freeCompressionContext
+#define LZ4F_freeDecompressionContext lz4_sys_LZ4F_freeDecompressionContext
+#define LZ4F_getBlockSize lz4_sys_LZ4F_getBlockSize
+#define LZ4F_getErrorCode lz4_sys_LZ4F_getErrorCode
+#define LZ4F_getErrorName lz4_sys_LZ4F_getErrorName
+#define LZ4F_getFrameInfo lz4_sys_LZ4F_getFrameInfo
+#define LZ4F_getVersion lz4_sys_LZ4F_getVersion
+#define LZ4F_headerSize lz4_sys_LZ4F_headerSize
+#define LZ4F_isError lz4_sys_LZ4F_isError
+#define LZ4F_resetDecompressionContext lz4_sys_LZ4F_resetDecompressionContext
+#define LZ4F_uncompressedUpdate lz4_sys_LZ4F_uncompressedUpdate
+#define LZ4HC_searchExtDict lz4_sys_LZ4HC_searchExtDict
+#define LZ4_attach_HC_dictionary lz4_sys_LZ4_attach_HC_dictionary
+#define LZ4_attach_dictionary lz4_sys_LZ4_attach_dictionary
+#define LZ4_compress lz4_sys_LZ4_compress
+#define LZ4_compressBound lz4_sys_LZ4_compressBound
+#define LZ4_compressHC lz4_sys_LZ4_compressHC
+#define LZ4_compressHC2 lz4_sys_LZ4_compressHC2
+#define LZ4_compressHC2_continue lz4_sys_LZ4_compressHC2_continue
+#define LZ4_compressHC2_limitedOutput lz4_sys_LZ4_compressHC2_limitedOutput
+#define LZ4_compressHC2_limitedOutput_continue lz4_sys_LZ4_compressHC2_limitedOutput_continue
+#define LZ4_compressHC2_limitedOutput_withStateHC lz4_sys_LZ4_compressHC2_limitedOutput_withStateHC
+#define LZ4_compressHC2_withStateHC lz4_sys_LZ4_compressHC2_withStateHC
+#define LZ4_compressHC_continue lz4_sys_LZ4_compressHC_continue
+#define LZ4_compressHC_limitedOutput lz4_sys_LZ4_compressHC_limitedOutput
+#define LZ4_compressHC_limitedOutput_continue lz4_sys_LZ4_compressHC_limitedOutput_continue
+#define LZ4_compressHC_limitedOutput_withStateHC lz4_sys_LZ4_compressHC_limitedOutput_withStateHC
+#define LZ4_compressHC_withStateHC lz4_sys_LZ4_compressHC_withStateHC
+#define LZ4_compress_HC lz4_sys_LZ4_compress_HC
+#define LZ4_compress_HC_continue lz4_sys_LZ4_compress_HC_continue
+#define LZ4_compress_HC_continue_destSize lz4_sys_LZ4_compress_HC_continue_destSize
+#define LZ4_compress_HC_destSize lz4_sys_LZ4_compress_HC_destSize
+#define LZ4_compress_HC_extStateHC lz4_sys_LZ4_compress_HC_extStateHC
+#define LZ4_compress_HC_extStateHC_fastReset lz4_sys_LZ4_compress_HC_extStateHC_fastReset
+#define LZ4_compress_continue lz4_sys_LZ4_compress_continue
+#define LZ4_compress_default lz4_sys_LZ4_compress_default
+#define LZ4_compress_destSize lz4_sys_LZ4_compress_destSize
+#define LZ4_compress_destSize_extState lz4_sys_LZ4_compress_destSize_extState
+#define LZ4_compress_fast lz4_sys_LZ4_compress_fast
+#define LZ4_compress_fast_continue lz4_sys_LZ4_compress_fast_continue
+#define LZ4_compress_fast_extState lz4_sys_LZ4_compress_fast_extState
+#define LZ4_compress_fast_extState_fastReset lz4_sys_LZ4_compress_fast_extState_fastReset
+#define LZ4_compress_fast_force lz4_sys_LZ4_compress_fast_force
+#define LZ4_compress_forceExtDict lz4_sys_LZ4_compress_forceExtDict
+#define LZ4_compress_limitedOutput lz4_sys_LZ4_compress_limitedOutput
+#define LZ4_compress_limitedOutput_continue lz4_sys_LZ4_compress_limitedOutput_continue
+#define LZ4_compress_limitedOutput_withState lz4_sys_LZ4_compress_limitedOutput_withState
+#define LZ4_compress_withState lz4_sys_LZ4_compress_withState
+#define LZ4_create lz4_sys_LZ4_create
+#define LZ4_createHC lz4_sys_LZ4_createHC
+#define LZ4_createStream lz4_sys_LZ4_createStream
+#define LZ4_createStreamDecode lz4_sys_LZ4_createStreamDecode
+#define LZ4_createStreamHC lz4_sys_LZ4_createStreamHC
+#define LZ4_decoderRingBufferSize lz4_sys_LZ4_decoderRingBufferSize
+#define LZ4_decompress_fast lz4_sys_LZ4_decompress_fast
+#define LZ4_decompress_fast_continue lz4_sys_LZ4_decompress_fast_continue
+#define LZ4_decompress_fast_usingDict lz4_sys_LZ4_decompress_fast_usingDict
+#define LZ4_decompress_fast_withPrefix64k lz4_sys_LZ4_decompress_fast_withPrefix64k
+#define LZ4_decompress_safe lz4_sys_LZ4_decompress_safe
+#define LZ4_decompress_safe_continue lz4_sys_LZ4_decompress_safe_continue
+#define LZ4_decompress_safe_forceExtDict lz4_sys_LZ4_decompress_safe_forceExtDict
+#define LZ4_decompress_safe_partial lz4_sys_LZ4_decompress_safe_partial
+#define LZ4_decompress_safe_partial_forceExtDict lz4_sys_LZ4_decompress_safe_partial_forceExtDict
+#define LZ4_decompress_safe_partial_usingDict lz4_sys_LZ4_decompress_safe_partial_usingDict
+#define LZ4_decompress_safe_usingDict lz4_sys_LZ4_decompress_safe_usingDict
+#define LZ4_decompress_safe_withPrefix64k lz4_sys_LZ4_decompress_safe_withPrefix64k
+#define LZ4_favorDecompressionSpeed lz4_sys_LZ4_favorDecompressionSpeed
+#define LZ4_freeHC lz4_sys_LZ4_freeHC
+#define LZ4_freeStream lz4_sys_LZ4_freeStream
+#define LZ4_freeStreamDecode lz4_sys_LZ4_freeStreamDecode
+#define LZ4_freeStreamHC lz4_sys_LZ4_freeStreamHC
+#define LZ4_initStream lz4_sys_LZ4_initStream
+#define LZ4_initStreamHC lz4_sys_LZ4_initStreamHC
+#define LZ4_loadDict lz4_sys_LZ4_loadDict
+#define LZ4_loadDictHC lz4_sys_LZ4_loadDictHC
+#define LZ4_loadDictSlow lz4_sys_LZ4_loadDictSlow
+#define LZ4_loadDict_internal lz4_sys_LZ4_loadDict_internal
+#define LZ4_resetStream lz4_sys_LZ4_resetStream
+#define LZ4_resetStreamHC lz4_sys_LZ4_resetStreamHC
+#define LZ4_resetStreamHC_fast lz4_sys_LZ4_resetStreamHC_fast
+#define LZ4_resetStreamState lz4_sys_LZ4_resetStreamState
+#define LZ4_resetStreamStateHC lz4_sys_LZ4_resetStreamStateHC
+#define LZ4_resetStream_fast lz4_sys_LZ4_resetStream_fast
+#define LZ4_saveDict lz4_sys_LZ4_saveDict
+#define LZ4_saveDictHC lz4_sys_LZ4_saveDictHC
+#define LZ4_setCompressionLevel lz4_sys_LZ4_setCompressionLevel
+#define LZ4_setStreamDecode lz4_sys_LZ4_setStreamDecode
+#define LZ4_sizeofState lz4_sys_LZ4_sizeofState
+#define LZ4_sizeofStateHC lz4_sys_LZ4_sizeofStateHC
+#define LZ4_sizeofStreamState lz4_sys_LZ4_sizeofStreamState
+#define LZ4_sizeofStreamStateHC lz4_sys_LZ4_sizeofStreamStateHC
+#define LZ4_slideInputBuffer lz4_sys_LZ4_slideInputBuffer
+#define LZ4_slideInputBufferHC lz4_sys_LZ4_slideInputBufferHC
+#define LZ4_uncompress lz4_sys_LZ4_uncompress
+#define LZ4_uncompress_unknownOutputSize lz4_sys_LZ4_uncompress_unknownOutputSize
+#define LZ4_versionNumber lz4_sys_LZ4_versionNumber
+#define LZ4_versionString lz4_sys_LZ4_versionString
+#define XXH32 lz4_sys_XXH32
+#define XXH32_canonicalFromHash lz4_sys_XXH32_canonicalFromHash
+#define XXH32_copyState lz4_sys_XXH32_copyState
+#define XXH32_createState lz4_sys_XXH32_createState
+#define XXH32_digest lz4_sys_XXH32_digest
+#define XXH32_freeState lz4_sys_XXH32_freeState
+#define XXH32_hashFromCanonical lz4_sys_XXH32_hashFromCanonical
+#define XXH32_reset lz4_sys_XXH32_reset
+#define XXH32_update lz4_sys_XXH32_update
+#define XXH64 lz4_sys_XXH64
+#define XXH64_canonicalFromHash lz4_sys_XXH64_canonicalFromHash
+#define XXH64_copyState lz4_sys_XXH64_copyState
+#define XXH64_createState lz4_sys_XXH64_createState
+#define XXH64_digest lz4_sys_XXH64_digest
+#define XXH64_freeState lz4_sys_XXH64_freeState
+#define XXH64_hashFromCanonical lz4_sys_XXH64_hashFromCanonical
+#define XXH64_reset lz4_sys_XXH64_reset
+#define XXH64_update lz4_sys_XXH64_update
+#define XXH_versionNumber lz4_sys_XXH_versionNumber
+#define read_long_length_no_check lz4_sys_read_long_length_no_check
+
+#endif /* LZ4_SYS_PREFIX_H */
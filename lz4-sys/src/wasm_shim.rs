@@ -0,0 +1,78 @@
+// Allocation functions for the vendored liblz4 on wasm32-unknown-unknown, which has no C library.
+// The headers in wasm-shim/ route malloc(), calloc() and free() here.
+use alloc::alloc::{alloc, alloc_zeroed, dealloc, Layout};
+use core::ptr;
+use libc::{c_void, size_t};
+
+// Each block starts with its total size, free() needs it to rebuild the layout. The header is as
+// large as the alignment malloc() guarantees, so the data after it stays aligned.
+const HEADER: usize = 16;
+
+unsafe fn allocate(size: size_t, zeroed: bool) -> *mut c_void {
+    let layout = match size
+        .checked_add(HEADER)
+        .and_then(|total| Layout::from_size_align(total, HEADER).ok())
+    {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    let block = if zeroed {
+        alloc_zeroed(layout)
+    } else {
+        alloc(layout)
+    };
+    if block.is_null() {
+        return ptr::null_mut();
+    }
+    *(block as *mut usize) = layout.size();
+    block.add(HEADER) as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4_sys_malloc(size: size_t) -> *mut c_void {
+    allocate(size, false)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4_sys_calloc(count: size_t, size: size_t) -> *mut c_void {
+    match count.checked_mul(size) {
+        Some(total) => allocate(total, true),
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4_sys_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let block = (ptr as *mut u8).sub(HEADER);
+    let size = *(block as *const usize);
+    dealloc(block, Layout::from_size_align_unchecked(size, HEADER));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocation() {
+        unsafe {
+            for &size in [0, 1, 15, 16, 4096, 1 << 20].iter() {
+                let ptr = lz4_sys_malloc(size) as *mut u8;
+                assert!(!ptr.is_null());
+                assert_eq!(ptr as usize % HEADER, 0);
+                ptr::write_bytes(ptr, 0xA5, size);
+                lz4_sys_free(ptr as *mut c_void);
+            }
+
+            let ptr = lz4_sys_calloc(100, 3) as *const u8;
+            assert!((0..300).all(|i| *ptr.add(i) == 0));
+            lz4_sys_free(ptr as *mut c_void);
+
+            lz4_sys_free(ptr::null_mut());
+            assert!(lz4_sys_malloc(usize::max_value()).is_null());
+            assert!(lz4_sys_calloc(usize::max_value(), 2).is_null());
+        }
+    }
+}
@@ -0,0 +1,8 @@
+/* Stand-in for the C library header on wasm32-unknown-unknown, which has none. liblz4 only
+ * asserts in debug builds. */
+#ifndef LZ4_SYS_WASM_ASSERT_H
+#define LZ4_SYS_WASM_ASSERT_H
+
+#define assert(condition) ((void)0)
+
+#endif /* LZ4_SYS_WASM_ASSERT_H */
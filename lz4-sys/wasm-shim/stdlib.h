@@ -0,0 +1,17 @@
+/* Stand-in for the C library header on wasm32-unknown-unknown, which has none. The allocation
+ * functions are implemented in Rust, see src/wasm_shim.rs; the prefixed names keep them from
+ * clashing with the malloc() of other C code in the same module. */
+#ifndef LZ4_SYS_WASM_STDLIB_H
+#define LZ4_SYS_WASM_STDLIB_H
+
+#include <stddef.h>
+
+void* lz4_sys_malloc(size_t size);
+void* lz4_sys_calloc(size_t count, size_t size);
+void lz4_sys_free(void* ptr);
+
+#define malloc lz4_sys_malloc
+#define calloc lz4_sys_calloc
+#define free lz4_sys_free
+
+#endif /* LZ4_SYS_WASM_STDLIB_H */
@@ -0,0 +1,13 @@
+/* Stand-in for the C library header on wasm32-unknown-unknown, which has none. The functions
+ * are provided by the Rust compiler builtins. */
+#ifndef LZ4_SYS_WASM_STRING_H
+#define LZ4_SYS_WASM_STRING_H
+
+#include <stddef.h>
+
+void* memcpy(void* dst, const void* src, size_t size);
+void* memmove(void* dst, const void* src, size_t size);
+void* memset(void* dst, int value, size_t size);
+int memcmp(const void* a, const void* b, size_t size);
+
+#endif /* LZ4_SYS_WASM_STRING_H */
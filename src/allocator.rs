@@ -0,0 +1,318 @@
+//! Custom allocation of the liblz4 frame contexts. By default liblz4 allocates its contexts with
+//! the C `malloc()`, out of sight of the Rust allocator. An allocator set with
+//! `EncoderBuilder::allocator()` or `DecoderBuilder::allocator()`, or given to
+//! `frame::decompress_with_allocator()`, is used instead for the context and every buffer liblz4
+//! allocates for it, so memory can be accounted and capped per user.
+//!
+//! The same mechanism lets `Encoder::with_state()` place the context in a caller provided
+//! `block::StateBuf` instead.
+//!
+//! Only available with the `allocator` feature, which builds the vendored liblz4 and needs it to
+//! be at least 1.9.4.
+//!
+//! # Examples
+//! ```
+//! use lz4::EncoderBuilder;
+//! use lz4::frame::FrameEncoder;
+//! use std::alloc::System;
+//! use std::sync::Arc;
+//!
+//! let encoder = FrameEncoder::new(EncoderBuilder::new().allocator(Arc::new(System))).unwrap();
+//! ```
+
+#[cfg(feature = "std")]
+use super::block::StateBuf;
+use super::error::{Error, ErrorKind, Result};
+use super::liblz4::*;
+use alloc::alloc::{alloc as heap_alloc, alloc_zeroed as heap_alloc_zeroed, dealloc as heap_dealloc};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::alloc::{GlobalAlloc, Layout};
+#[cfg(feature = "std")]
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libc::{c_void, size_t};
+
+/// Allocator of the liblz4 contexts, shared by the builders and the contexts they create.
+pub type Allocator = Arc<dyn GlobalAlloc + Send + Sync>;
+
+// liblz4 frees blocks by address only, so each block starts with its total size. The header is as
+// large as the alignment malloc() guarantees, so the data after it stays aligned.
+const HEADER: usize = 16;
+
+/// Keeps the allocator alive, at a stable address, for as long as the context using it.
+pub(crate) struct CustomMem(Box<Allocator>);
+
+impl CustomMem {
+    pub(crate) fn new(allocator: &Allocator) -> CustomMem {
+        CustomMem(Box::new(allocator.clone()))
+    }
+
+    pub(crate) fn raw(&self) -> LZ4FCustomMem {
+        LZ4FCustomMem {
+            custom_alloc: Some(custom_alloc),
+            custom_calloc: Some(custom_calloc),
+            custom_free: Some(custom_free),
+            opaque_state: &*self.0 as *const Allocator as *mut c_void,
+        }
+    }
+}
+
+/// Custom memory functions handing out the state buffer. Nothing is freed: the whole buffer is
+/// reclaimed when the `StateBuf` is reused. Only used by `Encoder::with_state()`.
+#[cfg(feature = "std")]
+pub(crate) fn arena(state: *mut StateBuf) -> LZ4FCustomMem {
+    LZ4FCustomMem {
+        custom_alloc: Some(arena_alloc),
+        custom_calloc: Some(arena_calloc),
+        custom_free: Some(arena_free),
+        opaque_state: state as *mut c_void,
+    }
+}
+
+/// Borrow of the state buffer an `Encoder` created by `Encoder::with_state()` keeps its context
+/// in. liblz4 reaches the buffer through the pointer it was given, so the borrow is held as that
+/// same raw pointer rather than as a `&mut StateBuf` aliasing it.
+#[cfg(feature = "std")]
+pub struct StateBorrow<'a, 'b: 'a> {
+    _state: *mut StateBuf<'b>,
+    _borrow: PhantomData<&'a mut StateBuf<'b>>,
+}
+
+// Only the context, which moves along with the borrow, accesses the buffer
+#[cfg(feature = "std")]
+unsafe impl<'a, 'b> Send for StateBorrow<'a, 'b> {}
+
+#[cfg(feature = "std")]
+impl<'a, 'b> StateBorrow<'a, 'b> {
+    pub(crate) fn new(state: *mut StateBuf<'b>) -> StateBorrow<'a, 'b> {
+        StateBorrow {
+            _state: state,
+            _borrow: PhantomData,
+        }
+    }
+}
+
+/// Heap allocator adding up what a context allocates, to size the `StateBuf` it needs.
+#[derive(Default)]
+pub(crate) struct Measure(AtomicUsize);
+
+impl Measure {
+    /// Arena size the allocations counted so far take. Each one needs less than its layout, which
+    /// includes the size header, plus the alignment of the first one.
+    pub(crate) fn total(&self) -> usize {
+        self.0.load(Ordering::SeqCst) + HEADER - 1
+    }
+}
+
+unsafe impl GlobalAlloc for Measure {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.fetch_add(layout.size(), Ordering::SeqCst);
+        heap_alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.0.fetch_add(layout.size(), Ordering::SeqCst);
+        heap_alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        heap_dealloc(ptr, layout)
+    }
+}
+
+/// liblz4 only reports a failed allocation through a null context.
+pub(crate) fn check_context(context: *mut c_void) -> Result<*mut c_void> {
+    if context.is_null() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "Allocator failed to allocate the LZ4 context.",
+        ));
+    }
+    Ok(context)
+}
+
+unsafe fn allocate(state: *mut c_void, size: size_t, zeroed: bool) -> *mut c_void {
+    let allocator = &*(state as *const Allocator);
+    let layout = match size
+        .checked_add(HEADER)
+        .and_then(|total| Layout::from_size_align(total, HEADER).ok())
+    {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    let block = if zeroed {
+        allocator.alloc_zeroed(layout)
+    } else {
+        allocator.alloc(layout)
+    };
+    if block.is_null() {
+        return ptr::null_mut();
+    }
+    *(block as *mut usize) = layout.size();
+    block.add(HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn custom_alloc(state: *mut c_void, size: size_t) -> *mut c_void {
+    allocate(state, size, false)
+}
+
+unsafe extern "C" fn custom_calloc(state: *mut c_void, size: size_t) -> *mut c_void {
+    allocate(state, size, true)
+}
+
+#[cfg(feature = "std")]
+unsafe extern "C" fn arena_alloc(state: *mut c_void, size: size_t) -> *mut c_void {
+    (*(state as *mut StateBuf)).allocate(size) as *mut c_void
+}
+
+#[cfg(feature = "std")]
+unsafe extern "C" fn arena_calloc(state: *mut c_void, size: size_t) -> *mut c_void {
+    let block = arena_alloc(state, size);
+    if !block.is_null() {
+        ptr::write_bytes(block as *mut u8, 0, size);
+    }
+    block
+}
+
+#[cfg(feature = "std")]
+unsafe extern "C" fn arena_free(_state: *mut c_void, _address: *mut c_void) {}
+
+unsafe extern "C" fn custom_free(state: *mut c_void, address: *mut c_void) {
+    if address.is_null() {
+        return;
+    }
+    let allocator = &*(state as *const Allocator);
+    let block = (address as *mut u8).sub(HEADER);
+    let size = *(block as *const usize);
+    allocator.dealloc(block, Layout::from_size_align_unchecked(size, HEADER));
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::super::encoder::EncoderBuilder;
+    use super::super::frame::{
+        compress, decompress, decompress_with_allocator, FrameDecoder, FrameEncoder,
+    };
+    use super::Allocator;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::io::{Read, Write};
+    use std::ptr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use DecoderBuilder;
+
+    // Counts the live bytes and refuses to go above the limit
+    struct Capped {
+        live: AtomicUsize,
+        peak: AtomicUsize,
+        limit: usize,
+    }
+
+    impl Capped {
+        fn new(limit: usize) -> Arc<Capped> {
+            Arc::new(Capped {
+                live: AtomicUsize::new(0),
+                peak: AtomicUsize::new(0),
+                limit,
+            })
+        }
+    }
+
+    unsafe impl GlobalAlloc for Capped {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let live = self.live.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            if live > self.limit {
+                self.live.fetch_sub(layout.size(), Ordering::SeqCst);
+                return ptr::null_mut();
+            }
+            self.peak.fetch_max(live, Ordering::SeqCst);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.live.fetch_sub(layout.size(), Ordering::SeqCst);
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn test_encoder_allocator() {
+        let capped = Capped::new(usize::MAX);
+        let data = vec![42u8; 300 * 1024];
+        {
+            let mut encoder = EncoderBuilder::new()
+                .allocator(capped.clone() as Allocator)
+                .build(Vec::new())
+                .unwrap();
+            encoder.write_all(&data).unwrap();
+            assert!(capped.live.load(Ordering::SeqCst) > 0);
+            let (compressed, result) = encoder.finish();
+            result.unwrap();
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        assert!(capped.peak.load(Ordering::SeqCst) > 64 * 1024);
+        assert_eq!(capped.live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_decoder_allocator() {
+        let capped = Capped::new(usize::MAX);
+        let data = vec![42u8; 300 * 1024];
+        let compressed = compress(&data, &EncoderBuilder::new()).unwrap();
+        {
+            let mut decoder = DecoderBuilder::new()
+                .allocator(capped.clone() as Allocator)
+                .build(&compressed[..])
+                .unwrap();
+            let mut actual = Vec::new();
+            decoder.read_to_end(&mut actual).unwrap();
+            assert_eq!(actual, data);
+        }
+        assert!(capped.peak.load(Ordering::SeqCst) > 0);
+        assert_eq!(capped.live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_frame_allocator() {
+        let capped = Capped::new(usize::MAX);
+        let data = vec![42u8; 300 * 1024];
+        let builder = EncoderBuilder::new()
+            .allocator(capped.clone() as Allocator)
+            .clone();
+        let compressed = compress(&data, &builder).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert!(capped.peak.load(Ordering::SeqCst) > 0);
+        assert_eq!(capped.live.load(Ordering::SeqCst), 0);
+
+        let capped = Capped::new(usize::MAX);
+        assert_eq!(
+            decompress_with_allocator(&compressed, &(capped.clone() as Allocator)).unwrap(),
+            data
+        );
+        assert!(capped.peak.load(Ordering::SeqCst) > 0);
+        assert_eq!(capped.live.load(Ordering::SeqCst), 0);
+        let capped = Capped::new(0);
+        assert!(decompress_with_allocator(&compressed, &(capped as Allocator)).is_err());
+    }
+
+    #[test]
+    fn test_allocator_limit() {
+        // Enough for the context, not for the block buffers
+        let capped = Capped::new(4096);
+        let builder = EncoderBuilder::new()
+            .allocator(capped.clone() as Allocator)
+            .clone();
+        let mut encoder = FrameEncoder::new(&builder).unwrap();
+        let mut dst = vec![0u8; 64];
+        assert!(encoder.begin(&mut dst).is_err());
+        drop(encoder);
+        assert_eq!(capped.live.load(Ordering::SeqCst), 0);
+
+        let capped = Capped::new(0);
+        let builder = EncoderBuilder::new().allocator(capped.clone() as Allocator).clone();
+        assert!(FrameEncoder::new(&builder).is_err());
+        assert!(FrameDecoder::with_allocator(&(capped as Allocator)).is_err());
+    }
+}
@@ -1,53 +1,1014 @@
 extern crate lz4;
 
+use lz4::block::{self, CompressionMode};
+use lz4::frame::{self, FrameReport};
+use lz4::liblz4::BlockChecksum;
+use lz4::util::SparseWriter;
+use lz4::{BlockMode, BlockSize, ContentChecksum, Decoder, EncoderBuilder};
+use std::cell::Cell;
 use std::env;
-use std::fs::File;
-use std::io::Read;
-use std::io::Result;
-use std::io::Write;
-use std::iter::FromIterator;
-use std::path::Path;
+use std::fs::{self, File, FileTimes, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, IsTerminal, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+const EXTENSION: &str = ".lz4";
+const STDIN_MARK: &str = "stdin";
+const STDOUT_MARK: &str = "stdout";
+const MAX_LEVEL: u32 = 12;
+const BUFFER_SIZE: usize = 64 * 1024;
+const BENCH_ITERATIONS: u32 = 3;
+const DEFAULT_VERBOSITY: i32 = 2;
+const PROGRESS_REFRESH: Duration = Duration::from_millis(200);
+
+// Prints to stderr when the verbosity is at least the given level: 1 for
+// errors, 2 for results, 3 and more for details.
+macro_rules! display {
+    ($verbosity:expr, $level:expr, $($arg:tt)*) => {
+        if $verbosity >= $level {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Auto,
+    Compress,
+    Decompress,
+    Test,
+    List,
+    Bench,
+}
+
+struct Options {
+    mode: Mode,
+    stdout: bool,
+    force: bool,
+    remove: bool,
+    multiple: bool,
+    recursive: bool,
+    follow_symlinks: bool,
+    output_dir: Option<PathBuf>,
+    content_size: bool,
+    sparse: bool,
+    verbosity: i32,
+    builder: EncoderBuilder,
+    // Kept apart from the builder for the benchmark, which also uses the block API
+    // Negative levels are the --fast accelerations
+    level: i32,
+    block_size: BlockSize,
+    bench_last: Option<i32>,
+    iterations: u32,
+    files: Vec<String>,
+}
+
+struct Job {
+    input: String,
+    output: Option<String>,
+    // Input path relative to the directory given on the command line, used
+    // to lay out files under --output-dir
+    relative: PathBuf,
+}
+
+enum Command {
+    Run(Options),
+    Help,
+    Version,
+}
 
 fn main() {
-    println!("LZ4 version: {}", lz4::version());
-    let suffix = ".lz4";
-    for arg in Vec::from_iter(env::args())[1..].iter() {
-        if arg.ends_with(suffix) {
-            decompress(
-                &Path::new(arg),
-                &Path::new(&arg[0..arg.len() - suffix.len()]),
-            ).unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", usage());
+            return;
+        }
+        Ok(Command::Version) => {
+            println!(
+                "*** LZ4 command line interface, using liblz4 {} ***",
+                version()
+            );
+            return;
+        }
+        Err(message) => {
+            eprintln!("lz4: {}", message);
+            eprint!("{}", usage());
+            process::exit(1);
+        }
+    };
+    process::exit(run(&options));
+}
+
+fn usage() -> String {
+    format!(
+        "Usage : lz4 [arg] [input] [output]
+
+input   : a filename; with no FILE, or when FILE is - or {stdin}, read standard input
+Arguments :
+ -1     : fast compression (default)
+ -9     : high compression
+ -12    : max compression
+--fast[=#]: switch to ultra fast compression level (default: 1)
+ -d     : decompression (default for {ext} extension)
+ -z     : force compression
+ -t     : test compressed file integrity
+--list  : lists information about .lz4 files
+ -b#    : benchmark file(s), using # compression level (default : 1)
+ -e#    : test all compression levels from -bX to # (default : 1)
+ -i#    : number of benchmark iterations (default : {iterations})
+ -c     : force write to standard output, even if it is the console
+ -f     : overwrite output without prompting
+ -k     : preserve source file(s) (default)
+--rm    : remove source file(s) after successful de/compression
+ -m     : multiple input files (implies automatic output filenames)
+ -r     : operate recursively on directories (sets also -m)
+--output-dir DIR : write output files into DIR, mirroring input directories
+--follow-symlinks : follow symbolic links found in directories (default: skip)
+ -B#    : block size [4-7] (default : 7)
+ -BD    : block dependency (improves compression ratio)
+--content-size : compressed frame includes original size (default:not present)
+--no-frame-crc : disable stream checksum (default:enabled)
+--sparse : sparse mode when decompressing to a file (default:enabled)
+--no-sparse : write zeros when decompressing to a file
+ -v     : verbose mode
+ -q     : suppress warnings; specify twice to suppress errors too
+ -h/-H  : display help and exit
+ -V     : display version number and exit
+",
+        stdin = STDIN_MARK,
+        ext = EXTENSION,
+        iterations = BENCH_ITERATIONS
+    )
+}
+
+fn version() -> String {
+    let version = lz4::version();
+    format!(
+        "{}.{}.{}",
+        version / 10000,
+        version / 100 % 100,
+        version % 100
+    )
+}
+
+fn parse_args(args: &[String]) -> std::result::Result<Command, String> {
+    let mut options = Options {
+        mode: Mode::Auto,
+        stdout: false,
+        force: false,
+        remove: false,
+        multiple: false,
+        recursive: false,
+        follow_symlinks: false,
+        output_dir: None,
+        content_size: false,
+        sparse: true,
+        verbosity: DEFAULT_VERBOSITY,
+        builder: EncoderBuilder::new(),
+        level: 1,
+        block_size: BlockSize::Max4MB,
+        bench_last: None,
+        iterations: BENCH_ITERATIONS,
+        files: Vec::new(),
+    };
+    // Same defaults as the reference tool: 4 MB independent blocks
+    options
+        .builder
+        .block_size(BlockSize::Max4MB)
+        .block_mode(BlockMode::Independent);
+
+    let mut only_files = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with('-') {
+            options.files.push(arg.clone());
+            continue;
+        }
+        if let Some(acceleration) = arg.strip_prefix("--fast=") {
+            match acceleration.parse::<i32>() {
+                Ok(acceleration) if acceleration > 0 => options.level = -acceleration,
+                _ => return Err(format!("invalid acceleration in {}", arg)),
+            }
+            options.builder.compression_level(options.level.into());
+            continue;
+        }
+        if let Some(dir) = arg.strip_prefix("--output-dir=") {
+            options.output_dir = Some(PathBuf::from(dir));
+            continue;
+        }
+        if arg.starts_with("--") {
+            match arg.as_str() {
+                "--" => only_files = true,
+                "--compress" => options.mode = Mode::Compress,
+                "--fast" => {
+                    options.level = -1;
+                    options.builder.compression_level(options.level.into());
+                }
+                "--decompress" | "--uncompress" => options.mode = Mode::Decompress,
+                "--test" => options.mode = Mode::Test,
+                "--list" => options.mode = Mode::List,
+                "--stdout" | "--to-stdout" => options.stdout = true,
+                "--force" => options.force = true,
+                "--keep" => options.remove = false,
+                "--rm" => options.remove = true,
+                "--multiple" => options.multiple = true,
+                "--quiet" => options.verbosity -= 1,
+                "--verbose" => options.verbosity += 1,
+                "--recursive" => options.recursive = true,
+                "--follow-symlinks" => options.follow_symlinks = true,
+                "--output-dir" => match args.next() {
+                    Some(dir) => options.output_dir = Some(PathBuf::from(dir)),
+                    None => return Err(String::from("--output-dir requires a directory")),
+                },
+                "--content-size" => options.content_size = true,
+                "--no-content-size" => options.content_size = false,
+                "--sparse" => options.sparse = true,
+                "--no-sparse" => options.sparse = false,
+                "--frame-crc" => {
+                    options.builder.checksum(ContentChecksum::ChecksumEnabled);
+                }
+                "--no-frame-crc" => {
+                    options.builder.checksum(ContentChecksum::NoChecksum);
+                }
+                "--help" => return Ok(Command::Help),
+                "--version" => return Ok(Command::Version),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+            continue;
+        }
+        let flags: Vec<char> = arg[1..].chars().collect();
+        let mut i = 0;
+        while i < flags.len() {
+            match flags[i] {
+                '0'..='9' => {
+                    options.level = read_number(&flags, &mut i).min(MAX_LEVEL) as i32;
+                    options.builder.compression_level(options.level.into());
+                    continue;
+                }
+                'B' => {
+                    i += 1;
+                    match flags.get(i) {
+                        Some(&'D') => {
+                            options.builder.block_mode(BlockMode::Linked);
+                            i += 1;
+                        }
+                        Some(c) if c.is_ascii_digit() => {
+                            let block_size = match read_number(&flags, &mut i) {
+                                4 => BlockSize::Max64KB,
+                                5 => BlockSize::Max256KB,
+                                6 => BlockSize::Max1MB,
+                                7 => BlockSize::Max4MB,
+                                n => return Err(format!("unsupported block size -B{}", n)),
+                            };
+                            options.builder.block_size(block_size.clone());
+                            options.block_size = block_size;
+                        }
+                        _ => return Err(format!("incomplete block option in {}", arg)),
+                    }
+                    continue;
+                }
+                'z' => options.mode = Mode::Compress,
+                'd' => options.mode = Mode::Decompress,
+                't' => options.mode = Mode::Test,
+                'b' => {
+                    options.mode = Mode::Bench;
+                    i += 1;
+                    if flags.get(i).is_some_and(|c| c.is_ascii_digit()) {
+                        options.level = read_number(&flags, &mut i).min(MAX_LEVEL) as i32;
+                    }
+                    continue;
+                }
+                'e' => {
+                    i += 1;
+                    options.bench_last = Some(read_number(&flags, &mut i).min(MAX_LEVEL) as i32);
+                    continue;
+                }
+                'i' => {
+                    i += 1;
+                    options.iterations = read_number(&flags, &mut i).max(1);
+                    continue;
+                }
+                'c' => options.stdout = true,
+                'f' => options.force = true,
+                'k' => options.remove = false,
+                'm' => options.multiple = true,
+                'q' => options.verbosity -= 1,
+                'v' => options.verbosity += 1,
+                'r' => options.recursive = true,
+                'h' | 'H' => return Ok(Command::Help),
+                'V' => return Ok(Command::Version),
+                c => return Err(format!("unknown option -{}", c)),
+            }
+            i += 1;
+        }
+    }
+    // Test, list and benchmark modes never write output, so every file name is an input
+    if options.recursive
+        || options.mode == Mode::Test
+        || options.mode == Mode::List
+        || options.mode == Mode::Bench
+    {
+        options.multiple = true;
+    }
+    if !options.multiple && options.files.len() > 2 {
+        return Err(String::from(
+            "too many file names, use -m for multiple input files",
+        ));
+    }
+    Ok(Command::Run(options))
+}
+
+fn read_number(flags: &[char], i: &mut usize) -> u32 {
+    let mut number: u32 = 0;
+    while let Some(digit) = flags.get(*i).and_then(|c| c.to_digit(10)) {
+        number = number.saturating_mul(10).saturating_add(digit);
+        *i += 1;
+    }
+    number
+}
+
+fn is_stdin(name: &str) -> bool {
+    name == "-" || name == STDIN_MARK
+}
+
+fn is_stdout(name: &str) -> bool {
+    name == "-" || name == STDOUT_MARK
+}
+
+/// Runs the requested operation and returns the process exit code: 0 on
+/// success, otherwise the number of files which could not be processed.
+fn run(options: &Options) -> i32 {
+    display!(
+        options.verbosity,
+        3,
+        "*** LZ4 command line interface, using liblz4 {} ***",
+        version()
+    );
+    let mut inputs = options.files.clone();
+    if inputs.is_empty() {
+        inputs.push(String::from(STDIN_MARK));
+    }
+    let mut failed = 0;
+    let mut skipped = 0;
+    let mut jobs = Vec::new();
+    if options.multiple {
+        for input in inputs {
+            if options.recursive && Path::new(&input).is_dir() {
+                let root = PathBuf::from(&input);
+                if let Err(e) = collect_dir(options, &root, &root, &mut jobs, &mut skipped) {
+                    display!(options.verbosity, 1, "lz4: {}: {}", input, e);
+                    failed += 1;
+                }
+            } else {
+                jobs.push(Job {
+                    relative: file_name(&input),
+                    input,
+                    output: None,
+                });
+            }
+        }
+    } else {
+        let input = inputs.swap_remove(0);
+        jobs.push(Job {
+            relative: file_name(&input),
+            input,
+            output: options.files.get(1).cloned(),
+        });
+    }
+
+    match options.mode {
+        Mode::List => {
+            println!(
+                "    Frames  Block      Checksum  Compressed  Uncompressed     Ratio   Filename"
+            )
+        }
+        Mode::Bench => println!("Level     API     Ratio      Compress    Decompress   Filename"),
+        _ => {}
+    }
+
+    let mut processed = 0;
+    let mut total = (0, 0);
+    for job in jobs.iter() {
+        let result = match options.mode {
+            Mode::Test => test_file(options, &job.input),
+            Mode::List => list_file(&job.input),
+            Mode::Bench => bench_file(options, &job.input),
+            _ => process_file(options, job).map(|(read, written)| {
+                total = (total.0 + read, total.1 + written);
+            }),
+        };
+        match result {
+            Ok(()) => processed += 1,
+            Err(e) => {
+                display!(options.verbosity, 1, "lz4: {}: {}", job.input, e);
+                failed += 1;
+            }
+        }
+    }
+    if options.recursive {
+        display!(
+            options.verbosity,
+            2,
+            "{} files processed, {} skipped, {} failed: {} bytes read, {} bytes written",
+            processed,
+            skipped,
+            failed,
+            total.0,
+            total.1
+        );
+    }
+    failed
+}
+
+/// Adds a job for every file under dir which the current mode applies to:
+/// `.lz4` files when decompressing, all other files when compressing.
+fn collect_dir(
+    options: &Options,
+    root: &Path,
+    dir: &Path,
+    jobs: &mut Vec<Job>,
+    skipped: &mut u64,
+) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            if !options.follow_symlinks {
+                *skipped += 1;
+                continue;
+            }
+            file_type = fs::metadata(&path)?.file_type();
+        }
+        if file_type.is_dir() {
+            collect_dir(options, root, &path, jobs, skipped)?;
+            continue;
+        }
+        let input = match path.to_str() {
+            Some(input) if file_type.is_file() => input.to_string(),
+            _ => {
+                *skipped += 1;
+                continue;
+            }
+        };
+        let compressed = input.ends_with(EXTENSION);
+        if compressed != (options.mode == Mode::Decompress) {
+            *skipped += 1;
+            continue;
+        }
+        jobs.push(Job {
+            relative: path.strip_prefix(root).unwrap_or(&path).to_path_buf(),
+            input,
+            output: None,
+        });
+    }
+    Ok(())
+}
+
+fn open_input(input: &str) -> Result<Box<dyn Read>> {
+    if is_stdin(input) {
+        if io::stdin().is_terminal() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "refusing to read from a console",
+            ));
+        }
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(File::open(input)?))
+    }
+}
+
+fn test_file(options: &Options, input: &str) -> Result<()> {
+    let report = frame::verify(open_input(input)?)?;
+    display!(
+        options.verbosity,
+        2,
+        "{:<30}: decoded {} bytes",
+        input,
+        report.uncompressed_size
+    );
+    Ok(())
+}
+
+fn list_file(input: &str) -> Result<()> {
+    if is_stdin(input) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "--list does not support standard input",
+        ));
+    }
+    let mut report = frame::scan(File::open(input)?)?;
+    if report.frames.iter().any(|f| f.content_size.is_none()) {
+        // Only a full decode tells the size of frames without content size
+        report = frame::verify(File::open(input)?)?;
+    }
+    println!(
+        "{:>10} {:>6} {:>13} {:>11} {:>13} {:>8.2}%   {}",
+        report.frames.len() as u64 + report.skippable_frames,
+        block_label(&report),
+        checksum_label(&report),
+        human_size(report.compressed_size),
+        human_size(report.uncompressed_size),
+        ratio(report.compressed_size, report.uncompressed_size),
+        input
+    );
+    Ok(())
+}
+
+/// Block size id and mode shared by all frames (e.g. `B7I`), `-` if they differ.
+fn block_label(report: &FrameReport) -> String {
+    let mut labels = report.frames.iter().map(|f| {
+        format!(
+            "B{}{}",
+            f.block_size.clone() as u32,
+            match f.block_mode {
+                BlockMode::Linked => 'D',
+                BlockMode::Independent => 'I',
+            }
+        )
+    });
+    match labels.next() {
+        Some(first) => {
+            if labels.all(|label| label == first) {
+                first
+            } else {
+                String::from("-")
+            }
+        }
+        None => String::from("-"),
+    }
+}
+
+/// Checksums present in all frames: `content`, `block`, both or `-`.
+fn checksum_label(report: &FrameReport) -> &'static str {
+    let frames = &report.frames;
+    let content = !frames.is_empty()
+        && frames
+            .iter()
+            .all(|f| f.content_checksum == ContentChecksum::ChecksumEnabled);
+    let block = !frames.is_empty()
+        && frames
+            .iter()
+            .all(|f| f.block_checksum == BlockChecksum::BlockChecksumEnabled);
+    match (content, block) {
+        (true, true) => "content,block",
+        (true, false) => "content",
+        (false, true) => "block",
+        (false, false) => "-",
+    }
+}
+
+fn human_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", size),
+        _ => format!("{:.2} {}", value, units[unit]),
+    }
+}
+
+fn bench_file(options: &Options, input: &str) -> Result<()> {
+    let mut data = Vec::new();
+    open_input(input)?.read_to_end(&mut data)?;
+    let first = options.level;
+    let last = options.bench_last.unwrap_or(first).max(first);
+    for level in first..last + 1 {
+        let block = bench_block(options, level, &data)?;
+        print_bench(level, "block", &data, block, input);
+        let frame = bench_frame(options, level, &data)?;
+        print_bench(level, "frame", &data, frame, input);
+    }
+    Ok(())
+}
+
+/// Best compressed size, compression and decompression time over all
+/// benchmark iterations.
+struct BenchResult {
+    compressed: usize,
+    compress: Duration,
+    decompress: Duration,
+}
+
+fn bench_block(options: &Options, level: i32, data: &[u8]) -> Result<BenchResult> {
+    // Same split as the frame format: independent blocks of the chosen size
+    let chunk = options.block_size.get_size();
+    let mut result = None;
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        let mut blocks = Vec::new();
+        for src in data.chunks(chunk) {
+            // Same acceleration as the frame API gives negative levels
+            let mode = match level {
+                level if level < 0 => CompressionMode::FAST(1 - level),
+                0..=2 => CompressionMode::DEFAULT,
+                _ => CompressionMode::HIGHCOMPRESSION(level),
+            };
+            blocks.push(block::compress(src, Some(mode), false)?);
+        }
+        let compress = start.elapsed();
+
+        let start = Instant::now();
+        let mut decompressed = Vec::with_capacity(data.len());
+        for (src, compressed) in data.chunks(chunk).zip(blocks.iter()) {
+            decompressed.extend(block::decompress(compressed, Some(src.len() as i32))?);
+        }
+        let decompress = start.elapsed();
+        check_bench(data, &decompressed)?;
+        result = Some(best(
+            result,
+            blocks.iter().map(|b| b.len()).sum(),
+            compress,
+            decompress,
+        ));
+    }
+    Ok(result.unwrap())
+}
+
+fn bench_frame(options: &Options, level: i32, data: &[u8]) -> Result<BenchResult> {
+    let mut builder = options.builder.clone();
+    builder.compression_level(level.into());
+    let mut result = None;
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        let mut encoder = builder.build(Vec::with_capacity(data.len()))?;
+        encoder.write_all(data)?;
+        let (compressed, finished) = encoder.finish();
+        finished?;
+        let compress = start.elapsed();
+
+        let start = Instant::now();
+        let mut decompressed = Vec::with_capacity(data.len());
+        Decoder::new(&compressed[..])?.read_to_end(&mut decompressed)?;
+        let decompress = start.elapsed();
+        check_bench(data, &decompressed)?;
+        result = Some(best(result, compressed.len(), compress, decompress));
+    }
+    Ok(result.unwrap())
+}
+
+fn best(
+    result: Option<BenchResult>,
+    compressed: usize,
+    compress: Duration,
+    decompress: Duration,
+) -> BenchResult {
+    match result {
+        Some(r) => BenchResult {
+            compressed,
+            compress: r.compress.min(compress),
+            decompress: r.decompress.min(decompress),
+        },
+        None => BenchResult {
+            compressed,
+            compress,
+            decompress,
+        },
+    }
+}
+
+fn check_bench(expected: &[u8], actual: &[u8]) -> Result<()> {
+    if expected != actual {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "benchmark round trip produced different data",
+        ));
+    }
+    Ok(())
+}
+
+fn print_bench(level: i32, api: &str, data: &[u8], result: BenchResult, input: &str) {
+    println!(
+        "{:>5}  {:>6}  {:>7.3}x  {:>7.1} MB/s  {:>7.1} MB/s   {}",
+        match level {
+            level if level < 0 => format!("--fast={}", -level),
+            level => format!("-{}", level),
+        },
+        api,
+        match result.compressed {
+            0 => 0.0,
+            size => data.len() as f64 / size as f64,
+        },
+        speed(data.len(), result.compress),
+        speed(data.len(), result.decompress),
+        input
+    );
+}
+
+fn speed(size: usize, time: Duration) -> f64 {
+    let seconds = time.as_secs() as f64 + time.subsec_nanos() as f64 / 1e9;
+    match seconds {
+        0.0 => 0.0,
+        _ => size as f64 / (1024.0 * 1024.0) / seconds,
+    }
+}
+
+/// Output path of a file given on the command line relative to --output-dir,
+/// its directories are dropped.
+fn file_name(input: &str) -> PathBuf {
+    match Path::new(input).file_name() {
+        Some(name) => PathBuf::from(name),
+        None => PathBuf::from(input),
+    }
+}
+
+fn output_name(mode: Mode, input: &str) -> Result<String> {
+    match mode {
+        Mode::Decompress if input.ends_with(EXTENSION) => {
+            Ok(input[..input.len() - EXTENSION.len()].to_string())
+        }
+        Mode::Decompress => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "cannot determine an output filename",
+        )),
+        _ => Ok(format!("{}{}", input, EXTENSION)),
+    }
+}
+
+fn process_file(options: &Options, job: &Job) -> Result<(u64, u64)> {
+    let input = job.input.as_str();
+    let mode = match options.mode {
+        Mode::Auto if input.ends_with(EXTENSION) => Mode::Decompress,
+        Mode::Auto => Mode::Compress,
+        mode => mode,
+    };
+    let output = match job.output {
+        _ if options.stdout => String::from(STDOUT_MARK),
+        Some(ref output) => output.clone(),
+        None if is_stdin(input) => String::from(STDOUT_MARK),
+        None => match options.output_dir {
+            Some(ref dir) => {
+                let relative = job.relative.to_string_lossy();
+                let output = dir.join(output_name(mode, &relative)?);
+                if let Some(parent) = output.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                output.to_string_lossy().into_owned()
+            }
+            None => output_name(mode, input)?,
+        },
+    };
+
+    let metadata = match is_stdin(input) {
+        true => None,
+        false => Some(fs::metadata(input)?),
+    };
+    let content_size = metadata.as_ref().map_or(0, |m| m.len());
+    let mut src = open_input(input)?;
+    // No result messages in pipe mode unless asked for
+    let verbosity = match options.verbosity {
+        DEFAULT_VERBOSITY if is_stdin(input) && is_stdout(&output) => 1,
+        verbosity => verbosity,
+    };
+    let progress = match verbosity >= 2 && io::stderr().is_terminal() {
+        true => Some(input),
+        false => None,
+    };
+
+    let result = if is_stdout(&output) {
+        if mode == Mode::Compress && !options.stdout && io::stdout().is_terminal() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "refusing to write compressed data to a console",
+            ));
+        }
+        let stdout = io::stdout();
+        let mut dst = stdout.lock();
+        transfer(options, mode, &mut src, &mut dst, content_size, progress)
+    } else {
+        let mut dst = if options.force {
+            File::create(&output)
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&output)
+        }
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists; not overwritten", output),
+            ),
+            _ => e,
+        })?;
+        let result = if mode == Mode::Decompress && options.sparse {
+            // Runs of zeros become holes instead of allocated blocks
+            let mut sparse = SparseWriter::new(&mut dst);
+            let result = transfer(options, mode, &mut src, &mut sparse, content_size, progress);
+            let (_, finished) = sparse.finish();
+            result.and_then(|r| finished.map(|()| r))
         } else {
-            compress(&Path::new(arg), &Path::new(&(arg.to_string() + suffix))).unwrap();
+            transfer(options, mode, &mut src, &mut dst, content_size, progress)
+        };
+        let result = result.and_then(|r| {
+            if let Some(ref metadata) = metadata {
+                copy_metadata(&dst, metadata)?;
+            }
+            Ok(r)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&output);
         }
+        result
+    };
+
+    let (read, written) = result?;
+    match mode {
+        Mode::Decompress => display!(verbosity, 2, "{:<30}: decoded {} bytes", input, written),
+        _ => display!(
+            verbosity,
+            2,
+            "Compressed {} bytes into {} bytes ==> {:.2}%",
+            read,
+            written,
+            ratio(written, read)
+        ),
+    }
+    if options.remove && !is_stdin(input) {
+        fs::remove_file(input)?;
+    }
+    Ok((read, written))
+}
+
+/// Gives the output file the permissions and access/modification times of
+/// its source.
+fn copy_metadata(dst: &File, metadata: &Metadata) -> Result<()> {
+    dst.set_permissions(metadata.permissions())?;
+    dst.set_times(
+        FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?),
+    )
+}
+
+fn ratio(compressed: u64, uncompressed: u64) -> f64 {
+    match uncompressed {
+        0 => 100.0,
+        _ => compressed as f64 * 100.0 / uncompressed as f64,
     }
 }
 
-fn compress(src: &Path, dst: &Path) -> Result<()> {
-    println!("Compressing: {:?} -> {:?}", src, dst);
-    let mut fi = try!(File::open(src));
-    let mut fo = try!(lz4::EncoderBuilder::new().build(try!(File::create(dst))));
-    try!(copy(&mut fi, &mut fo));
-    match fo.finish() {
-        (_, result) => result,
+/// Compresses or decompresses src into dst, returning the number of bytes
+/// read and written. When a file name is given for progress, the amount of
+/// processed data is displayed on stderr as the transfer goes.
+fn transfer(
+    options: &Options,
+    mode: Mode,
+    src: &mut dyn Read,
+    dst: &mut dyn Write,
+    content_size: u64,
+    progress: Option<&str>,
+) -> Result<(u64, u64)> {
+    let written = Cell::new(0);
+    let start = Instant::now();
+    let mut src = CountingReader {
+        r: src,
+        count: 0,
+        progress: progress.map(|name| Progress {
+            name,
+            written: &written,
+            start,
+            last: start,
+            shown: false,
+        }),
+    };
+    let mut dst = CountingWriter {
+        w: dst,
+        count: &written,
+    };
+    let result = match mode {
+        Mode::Decompress => decompress(&mut src, &mut dst),
+        _ => compress(options, &mut src, &mut dst, content_size),
     }
+    .and_then(|()| dst.flush());
+    if let Some(ref progress) = src.progress {
+        progress.clear();
+    }
+    result?;
+    Ok((src.count, written.get()))
 }
 
-fn decompress(src: &Path, dst: &Path) -> Result<()> {
-    println!("Decompressing: {:?} -> {:?}", src, dst);
-    let mut fi = try!(lz4::Decoder::new(try!(File::open(src))));
-    let mut fo = try!(File::create(dst));
-    copy(&mut fi, &mut fo)
+fn compress(
+    options: &Options,
+    src: &mut dyn Read,
+    dst: &mut dyn Write,
+    content_size: u64,
+) -> Result<()> {
+    let mut builder = options.builder.clone();
+    if options.content_size {
+        builder.content_size(content_size);
+    }
+    let mut encoder = builder.build(dst)?;
+    copy(src, &mut encoder)?;
+    let (_, result) = encoder.finish();
+    result
+}
+
+fn decompress(src: &mut dyn Read, dst: &mut dyn Write) -> Result<()> {
+    // The input may hold several concatenated frames, each one gets its own
+    // decoder. Decoder never reads past the end of a frame.
+    let mut input = BufReader::new(src);
+    loop {
+        let mut decoder = Decoder::new(&mut input)?;
+        copy(&mut decoder, dst)?;
+        let (_, result) = decoder.finish();
+        result?;
+        if input.fill_buf()?.is_empty() {
+            return Ok(());
+        }
+    }
 }
 
-fn copy(src: &mut Read, dst: &mut Write) -> Result<()> {
-    let mut buffer: [u8; 1024] = [0; 1024];
+fn copy(src: &mut dyn Read, dst: &mut dyn Write) -> Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
     loop {
-        let len = try!(src.read(&mut buffer));
+        let len = match src.read(&mut buffer) {
+            Ok(len) => len,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
         if len == 0 {
             break;
         }
-        try!(dst.write_all(&buffer[0..len]));
+        dst.write_all(&buffer[0..len])?;
     }
     Ok(())
 }
+
+struct Progress<'a> {
+    name: &'a str,
+    // Shared with the CountingWriter of the same transfer
+    written: &'a Cell<u64>,
+    start: Instant,
+    last: Instant,
+    shown: bool,
+}
+
+impl<'a> Progress<'a> {
+    fn update(&mut self, read: u64) {
+        let now = Instant::now();
+        if now.duration_since(self.last) < PROGRESS_REFRESH {
+            return;
+        }
+        self.last = now;
+        self.shown = true;
+        let written = self.written.get();
+        eprint!(
+            "\r{}: {} ==> {} ({:.2}%), {:.1} MB/s   ",
+            self.name,
+            human_size(read),
+            human_size(written),
+            ratio(written, read),
+            speed(read as usize, now.duration_since(self.start))
+        );
+    }
+
+    fn clear(&self) {
+        if self.shown {
+            eprint!("\r{:79}\r", "");
+        }
+    }
+}
+
+struct CountingReader<'a> {
+    r: &'a mut dyn Read,
+    count: u64,
+    progress: Option<Progress<'a>>,
+}
+
+impl<'a> Read for CountingReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.r.read(buf)?;
+        self.count += len as u64;
+        if let Some(ref mut progress) = self.progress {
+            progress.update(self.count);
+        }
+        Ok(len)
+    }
+}
+
+struct CountingWriter<'a> {
+    w: &'a mut dyn Write,
+    count: &'a Cell<u64>,
+}
+
+impl<'a> Write for CountingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = self.w.write(buf)?;
+        self.count.set(self.count.get() + len as u64);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.w.flush()
+    }
+}
@@ -18,9 +18,16 @@
 //! assert_eq!(v, decompress(&comp_wo_prefix, Some(1024)).unwrap());
 //! ```
 
+use super::error::{Error, ErrorKind, Result};
 use super::liblz4::*;
-use libc::c_char;
-use std::io::{Error, ErrorKind, Result};
+use alloc::vec::Vec;
+use core::cmp;
+use core::marker::PhantomData;
+use core::ptr;
+use libc::{c_char, c_void};
+
+// Alignment of the liblz4 states, and of the blocks malloc() returns
+const STATE_ALIGN: usize = 16;
 
 /// Represents the compression mode do be used.
 pub enum CompressionMode {
@@ -32,6 +39,97 @@ pub enum CompressionMode {
     DEFAULT,
 }
 
+/// Caller provided memory for the compression state, so that compressing does not allocate in
+/// liblz4: a buffer on the stack, in an arena, or reused from call to call. Used by
+/// `compress_with_state()`, the only user in default builds. `Encoder::with_state()` and
+/// `EncoderBuilder::state_size()`, which keep a frame context in it, need the `allocator` feature.
+///
+/// The state sizes depend on how liblz4 was built (`LZ4_SYS_MEMORY_USAGE`), use `fast_size()`,
+/// `hc_size()` or `EncoderBuilder::state_size()` rather than constants.
+pub struct StateBuf<'a> {
+    // liblz4 keeps pointers into the buffer between allocations, so after new() it is only
+    // reached through the base pointer, never through a reference that would invalidate them
+    base: *mut u8,
+    len: usize,
+    // Bytes handed out so far when used as an arena by the frame encoder
+    used: usize,
+    _buffer: PhantomData<&'a mut [u8]>,
+}
+
+// Owns its buffer borrow like a &mut [u8] would
+unsafe impl<'a> Send for StateBuf<'a> {}
+unsafe impl<'a> Sync for StateBuf<'a> {}
+
+impl<'a> StateBuf<'a> {
+    /// Wraps the buffer, it does not need to be aligned.
+    pub fn new(buffer: &'a mut [u8]) -> StateBuf<'a> {
+        StateBuf {
+            base: buffer.as_mut_ptr(),
+            len: buffer.len(),
+            used: 0,
+            _buffer: PhantomData,
+        }
+    }
+
+    /// Buffer size needed by the `FAST` and `DEFAULT` modes (`LZ4_sizeofState()` plus room for
+    /// the alignment).
+    pub fn fast_size() -> usize {
+        unsafe { LZ4_sizeofState() as usize + STATE_ALIGN - 1 }
+    }
+
+    /// Buffer size needed by the `HIGHCOMPRESSION` mode (`LZ4_sizeofStateHC()` plus room for the
+    /// alignment).
+    pub fn hc_size() -> usize {
+        unsafe { LZ4_sizeofStateHC() as usize + STATE_ALIGN - 1 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Forgets everything allocated from the buffer.
+    pub(crate) fn reset(&mut self) {
+        self.used = 0;
+    }
+
+    /// Returns the next aligned size bytes of the buffer, null once it is exhausted.
+    pub(crate) fn allocate(&mut self, size: usize) -> *mut u8 {
+        let start = self.base as usize;
+        let offset = ((start + self.used + STATE_ALIGN - 1) & !(STATE_ALIGN - 1)) - start;
+        match offset.checked_add(size) {
+            Some(end) if end <= self.len => {
+                self.used = end;
+                unsafe { self.base.add(offset) }
+            }
+            _ => ptr::null_mut(),
+        }
+    }
+}
+
+/// Returns the maximum size of the compressed output for an input of the given size, not counting
+/// the size prefix. A buffer of this size (plus 4 bytes when prepending the size) is always large
+/// enough for `compress_to_buffer()`.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the input size is too large.
+///
+pub fn compress_bound(uncompressed_size: usize) -> Result<usize> {
+    // 0 iff src too large
+    let compress_bound: i32 = unsafe { LZ4_compressBound(uncompressed_size as i32) };
+
+    if uncompressed_size > (i32::MAX as usize) || compress_bound <= 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Compression input too long.",
+        ));
+    }
+    Ok(compress_bound as usize)
+}
+
 /// Compresses the full src buffer using the specified CompressionMode, where None and Some(Default)
 /// are treated equally. If prepend_size is set, the source length will be prepended to the output
 /// buffer.
@@ -43,74 +141,182 @@ pub enum CompressionMode {
 /// this happens, the C api was not able to provide more information about the cause.
 ///
 pub fn compress(src: &[u8], mode: Option<CompressionMode>, prepend_size: bool) -> Result<Vec<u8>> {
-    // 0 iff src too large
-    let compress_bound: i32 = unsafe { LZ4_compressBound(src.len() as i32) };
+    let bound = try!(compress_bound(src.len()));
+    let mut compressed: Vec<u8> = vec![0; if prepend_size { bound + 4 } else { bound }];
+    let len = try!(compress_to_buffer(src, mode, prepend_size, &mut compressed));
+    compressed.truncate(len);
+    Ok(compressed)
+}
 
-    if src.len() > (i32::max_value() as usize) || compress_bound <= 0 {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "Compression input too long.",
-        ));
-    }
+/// Compresses the full src buffer into the given buffer, like `compress()`, and returns the number
+/// of bytes written. Does not allocate.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the src buffer is too long.
+/// Returns std::io::Error with ErrorKind::WriteZero if the compressed data does not fit into the
+/// buffer. A buffer of `compress_bound()` bytes (plus 4 with prepend_size) always fits.
+/// Returns std::io::Error with ErrorKind::Other if the compression failed inside the C library.
+///
+pub fn compress_to_buffer(
+    src: &[u8],
+    mode: Option<CompressionMode>,
+    prepend_size: bool,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    compress_into(src, mode, prepend_size, None, buffer)
+}
+
+/// Compresses the full src buffer into the given buffer like `compress_to_buffer()`, keeping the
+/// compression state in the given `StateBuf` instead of the stack (or the heap with the liblz4
+/// `heapmode` feature).
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the src buffer is too long or the state
+/// buffer is smaller than `StateBuf::fast_size()` (`StateBuf::hc_size()` for HIGHCOMPRESSION).
+/// Returns std::io::Error with ErrorKind::WriteZero if the compressed data does not fit into the
+/// buffer.
+/// Returns std::io::Error with ErrorKind::Other if the compression failed inside the C library.
+///
+pub fn compress_with_state(
+    src: &[u8],
+    mode: Option<CompressionMode>,
+    prepend_size: bool,
+    state: &mut StateBuf,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    compress_into(src, mode, prepend_size, Some(state), buffer)
+}
 
-    let mut compressed: Vec<u8> = vec![
-        0;
-        (if prepend_size {
-            compress_bound + 4
+/// Allocates the liblz4 state from the buffer and initializes it, null if it does not fit. liblz4
+/// checks the size and alignment it needs itself.
+fn init_state(state: &mut StateBuf, hc: bool) -> *mut c_void {
+    unsafe {
+        let size = if hc {
+            LZ4_sizeofStateHC()
         } else {
-            compress_bound
-        }) as usize
-    ];
-
-    let dec_size;
-    {
-        let dst_buf: &mut [u8];
-        if prepend_size {
-            let size = src.len() as u32;
-            compressed[0] = size as u8;
-            compressed[1] = (size >> 8) as u8;
-            compressed[2] = (size >> 16) as u8;
-            compressed[3] = (size >> 24) as u8;
-            dst_buf = &mut compressed[4..];
+            LZ4_sizeofState()
+        } as usize;
+        state.reset();
+        let buffer = state.allocate(size) as *mut c_void;
+        if buffer.is_null() {
+            return buffer;
+        }
+        if hc {
+            LZ4_initStreamHC(buffer, size) as *mut c_void
         } else {
-            dst_buf = &mut compressed;
+            LZ4_initStream(buffer, size) as *mut c_void
         }
+    }
+}
 
-        dec_size = match mode {
-            Some(CompressionMode::HIGHCOMPRESSION(level)) => unsafe {
-                LZ4_compress_HC(
-                    src.as_ptr() as *const c_char,
-                    dst_buf.as_mut_ptr() as *mut c_char,
-                    src.len() as i32,
-                    compress_bound,
-                    level,
-                )
-            },
-            Some(CompressionMode::FAST(accel)) => unsafe {
-                LZ4_compress_fast(
-                    src.as_ptr() as *const c_char,
-                    dst_buf.as_mut_ptr() as *mut c_char,
-                    src.len() as i32,
-                    compress_bound,
-                    accel,
-                )
-            },
-            _ => unsafe {
-                LZ4_compress_default(
-                    src.as_ptr() as *const c_char,
-                    dst_buf.as_mut_ptr() as *mut c_char,
-                    src.len() as i32,
-                    compress_bound,
-                )
-            },
-        };
+fn compress_into(
+    src: &[u8],
+    mode: Option<CompressionMode>,
+    prepend_size: bool,
+    state: Option<&mut StateBuf>,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    let bound = try!(compress_bound(src.len()));
+
+    let state = match state {
+        Some(state) => {
+            let hc = matches!(mode, Some(CompressionMode::HIGHCOMPRESSION(_)));
+            let state = init_state(state, hc);
+            if state.is_null() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "State buffer is too small.",
+                ));
+            }
+            state
+        }
+        None => ptr::null_mut(),
+    };
+
+    let dst_buf: &mut [u8];
+    if prepend_size {
+        if buffer.len() < 4 {
+            return Err(buffer_too_small());
+        }
+        let size = src.len() as u32;
+        buffer[0] = size as u8;
+        buffer[1] = (size >> 8) as u8;
+        buffer[2] = (size >> 16) as u8;
+        buffer[3] = (size >> 24) as u8;
+        dst_buf = &mut buffer[4..];
+    } else {
+        dst_buf = buffer;
     }
+    let capacity = cmp::min(dst_buf.len(), i32::MAX as usize) as i32;
+
+    let dec_size = match mode {
+        Some(CompressionMode::HIGHCOMPRESSION(level)) if !state.is_null() => unsafe {
+            LZ4_compress_HC_extStateHC(
+                state,
+                src.as_ptr() as *const c_char,
+                dst_buf.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                capacity,
+                level,
+            )
+        },
+        Some(CompressionMode::HIGHCOMPRESSION(level)) => unsafe {
+            LZ4_compress_HC(
+                src.as_ptr() as *const c_char,
+                dst_buf.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                capacity,
+                level,
+            )
+        },
+        Some(CompressionMode::FAST(accel)) if !state.is_null() => unsafe {
+            LZ4_compress_fast_extState(
+                state,
+                src.as_ptr() as *const c_char,
+                dst_buf.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                capacity,
+                accel,
+            )
+        },
+        Some(CompressionMode::FAST(accel)) => unsafe {
+            LZ4_compress_fast(
+                src.as_ptr() as *const c_char,
+                dst_buf.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                capacity,
+                accel,
+            )
+        },
+        // LZ4_compress_default() is the fast mode without acceleration
+        _ if !state.is_null() => unsafe {
+            LZ4_compress_fast_extState(
+                state,
+                src.as_ptr() as *const c_char,
+                dst_buf.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                capacity,
+                1,
+            )
+        },
+        _ => unsafe {
+            LZ4_compress_default(
+                src.as_ptr() as *const c_char,
+                dst_buf.as_mut_ptr() as *mut c_char,
+                src.len() as i32,
+                capacity,
+            )
+        },
+    };
     if dec_size <= 0 {
+        // With a buffer below the bound, liblz4 fails when the output does not fit
+        if dst_buf.len() < bound {
+            return Err(buffer_too_small());
+        }
         return Err(Error::new(ErrorKind::Other, "Compression failed"));
     }
 
-    compressed.truncate(if prepend_size { dec_size + 4 } else { dec_size } as usize);
-    Ok(compressed)
+    Ok(if prepend_size { dec_size + 4 } else { dec_size } as usize)
 }
 
 /// Decompresses the src buffer. If uncompressed_size is None, the source length will be read from
@@ -123,7 +329,57 @@ pub fn compress(src: &[u8], mode: Option<CompressionMode>, prepend_size: bool) -
 /// Returns std::io::Error with ErrorKind::InvalidData if the decompression failed inside the C
 /// library. This is most likely due to malformed input.
 ///
-pub fn decompress(mut src: &[u8], uncompressed_size: Option<i32>) -> Result<Vec<u8>> {
+pub fn decompress(src: &[u8], uncompressed_size: Option<i32>) -> Result<Vec<u8>> {
+    let (size, _) = try!(decompressed_size(src, uncompressed_size));
+    let mut decompressed = vec![0u8; size];
+    let len = try!(decompress_to_buffer(src, uncompressed_size, &mut decompressed));
+    decompressed.truncate(len);
+    Ok(decompressed)
+}
+
+/// Decompresses the src buffer into the given buffer, like `decompress()`, and returns the number
+/// of bytes written. Does not allocate.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the src buffer is too short, the
+/// provided (or parsed) uncompressed_size is to large or negative.
+/// Returns std::io::Error with ErrorKind::WriteZero if the buffer is shorter than the
+/// uncompressed size.
+/// Returns std::io::Error with ErrorKind::InvalidData if the decompression failed inside the C
+/// library. This is most likely due to malformed input.
+///
+pub fn decompress_to_buffer(
+    src: &[u8],
+    uncompressed_size: Option<i32>,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    let (size, src) = try!(decompressed_size(src, uncompressed_size));
+    if buffer.len() < size {
+        return Err(buffer_too_small());
+    }
+
+    let dec_bytes = unsafe {
+        LZ4_decompress_safe(
+            src.as_ptr() as *const c_char,
+            buffer.as_mut_ptr() as *mut c_char,
+            src.len() as i32,
+            size as i32,
+        )
+    };
+
+    if dec_bytes < 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Decompression failed. Input invalid or too long?",
+        ));
+    }
+
+    Ok(dec_bytes as usize)
+}
+
+/// Returns the uncompressed size, either the given one or the one read from the size prefix, and
+/// the compressed data following the prefix.
+fn decompressed_size(mut src: &[u8], uncompressed_size: Option<i32>) -> Result<(usize, &[u8])> {
     let size;
 
     if let Some(s) = uncompressed_size {
@@ -159,30 +415,22 @@ pub fn decompress(mut src: &[u8], uncompressed_size: Option<i32>) -> Result<Vec<
         ));
     }
 
-    let mut decompressed = vec![0u8; size as usize];
-    let dec_bytes = unsafe {
-        LZ4_decompress_safe(
-            src.as_ptr() as *const c_char,
-            decompressed.as_mut_ptr() as *mut c_char,
-            src.len() as i32,
-            size,
-        )
-    };
-
-    if dec_bytes < 0 {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "Decompression failed. Input invalid or too long?",
-        ));
-    }
+    Ok((size as usize, src))
+}
 
-    decompressed.truncate(dec_bytes as usize);
-    Ok(decompressed)
+fn buffer_too_small() -> Error {
+    Error::new(ErrorKind::WriteZero, "Destination buffer is too small.")
 }
 
 #[cfg(test)]
 mod test {
-    use block::{compress, decompress, CompressionMode};
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use block::{
+        compress, compress_bound, compress_to_buffer, compress_with_state, decompress,
+        decompress_to_buffer, CompressionMode, StateBuf,
+    };
+    use error::ErrorKind;
 
     #[test]
     fn test_compression_without_prefix() {
@@ -274,4 +522,70 @@ mod test {
 
         assert_eq!(decompress(&compressed, None).unwrap(), reference.as_bytes())
     }
+
+    #[test]
+    fn test_to_buffer() {
+        let src: Vec<u8> = (0..65536).map(|i| (i % 251) as u8).collect();
+        let mut compressed = vec![0u8; compress_bound(src.len()).unwrap() + 4];
+        let len = compress_to_buffer(&src, None, true, &mut compressed).unwrap();
+        assert_eq!(&compressed[..len], &compress(&src, None, true).unwrap()[..]);
+
+        let mut decompressed = vec![0u8; src.len()];
+        assert_eq!(
+            decompress_to_buffer(&compressed[..len], None, &mut decompressed).unwrap(),
+            src.len()
+        );
+        assert_eq!(decompressed, src);
+
+        let err = compress_to_buffer(&src, None, true, &mut compressed[..len / 2]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        let err = compress_to_buffer(&src, None, true, &mut [0u8; 3]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        let err = decompress_to_buffer(&compressed[..len], None, &mut decompressed[1..])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn test_compress_bound() {
+        assert!(compress_bound(1024).unwrap() >= 1024);
+        assert_eq!(
+            compress_bound(i32::MAX as usize + 1).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_with_state() {
+        let src: Vec<u8> = (0..65536).map(|i| (i % 251) as u8).collect();
+        let mut compressed = vec![0u8; compress_bound(src.len()).unwrap() + 4];
+        let mut memory = vec![0u8; StateBuf::hc_size() + 1];
+        // Unaligned on purpose
+        let mut state = StateBuf::new(&mut memory[1..]);
+
+        let modes = [
+            || None,
+            || Some(CompressionMode::DEFAULT),
+            || Some(CompressionMode::FAST(8)),
+            || Some(CompressionMode::HIGHCOMPRESSION(9)),
+        ];
+        for mode in modes.iter() {
+            let len = compress_with_state(&src, mode(), true, &mut state, &mut compressed).unwrap();
+            assert_eq!(&compressed[..len], &compress(&src, mode(), true).unwrap()[..]);
+            assert_eq!(decompress(&compressed[..len], None).unwrap(), src);
+        }
+
+        let mut memory = vec![0u8; StateBuf::fast_size()];
+        let mut state = StateBuf::new(&mut memory);
+        compress_with_state(&src, None, false, &mut state, &mut compressed).unwrap();
+        let err = compress_with_state(
+            &src,
+            Some(CompressionMode::HIGHCOMPRESSION(9)),
+            false,
+            &mut state,
+            &mut compressed,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
 }
@@ -1,14 +1,64 @@
+#[cfg(feature = "allocator")]
+use super::allocator::{check_context, Allocator, CustomMem};
+use super::error::Result;
+#[cfg(feature = "std")]
+use super::error::{Error, ErrorKind};
 use super::liblz4::*;
+use core::ptr;
+#[cfg(feature = "std")]
 use libc::size_t;
-use std::io::{Error, ErrorKind, Read, Result};
-use std::ptr;
+#[cfg(feature = "std")]
+use std::io::Read;
 
+#[cfg(feature = "std")]
 const BUFFER_SIZE: usize = 32 * 1024;
 
-struct DecoderContext {
-    c: LZ4FDecompressionContext,
+// Offset of the FLG byte inside the frame header (right after the magic number)
+#[cfg(feature = "std")]
+const FLG_OFFSET: usize = 4;
+// Content checksum bit of the FLG byte
+#[cfg(feature = "std")]
+const FLG_CONTENT_CHECKSUM: u8 = 0x04;
+
+// First liblz4 version honouring LZ4F_decompressOptions_t.skipChecksums
+#[cfg(feature = "std")]
+const SKIP_CHECKSUMS_VERSION: i32 = 10903;
+
+pub(crate) struct DecoderContext {
+    pub(crate) c: LZ4FDecompressionContext,
+    // Freed after the context, which allocates through it
+    #[cfg(feature = "allocator")]
+    _allocator: Option<CustomMem>,
 }
 
+/// How the decoder treats the content checksum of the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Verify the content checksum when the frame has one (default).
+    Verify,
+    /// Do not calculate nor verify the content checksum. Needs liblz4 v1.9.3 or later, older
+    /// versions fall back to `Verify`, which `Decoder::checksum_mode()` reports.
+    Skip,
+    /// Verify the content checksum and reject frames written without one.
+    Require,
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct DecoderBuilder {
+    checksum: ChecksumMode,
+    #[cfg(feature = "allocator")]
+    allocator: Option<Allocator>,
+}
+
+#[cfg(feature = "std")]
+impl Default for DecoderBuilder {
+    fn default() -> Self {
+        DecoderBuilder::new()
+    }
+}
+
+#[cfg(feature = "std")]
 pub struct Decoder<R> {
     c: DecoderContext,
     r: R,
@@ -16,29 +66,83 @@ pub struct Decoder<R> {
     pos: usize,
     len: usize,
     next: usize,
+    checksum: ChecksumMode,
+    // Number of frame header bytes passed to liblz4, tracked until FLG is known
+    header: usize,
 }
 
-impl<R: Read> Decoder<R> {
-    /// Creates a new encoder which will have its output written to the given
-    /// output stream. The output stream can be re-acquired by calling
-    /// `finish()`
-    pub fn new(r: R) -> Result<Decoder<R>> {
+#[cfg(feature = "std")]
+impl DecoderBuilder {
+    pub fn new() -> Self {
+        DecoderBuilder {
+            checksum: ChecksumMode::Verify,
+            #[cfg(feature = "allocator")]
+            allocator: None,
+        }
+    }
+
+    pub fn checksum(&mut self, checksum: ChecksumMode) -> &mut Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Allocates the decompression context through the given allocator instead of the C
+    /// `malloc()`, see the `allocator` module.
+    #[cfg(feature = "allocator")]
+    pub fn allocator(&mut self, allocator: Allocator) -> &mut Self {
+        self.allocator = Some(allocator);
+        self
+    }
+
+    fn context(&self) -> Result<DecoderContext> {
+        #[cfg(feature = "allocator")]
+        {
+            if let Some(ref allocator) = self.allocator {
+                return DecoderContext::with_allocator(allocator);
+            }
+        }
+        DecoderContext::new()
+    }
+
+    pub fn build<R: Read>(&self, r: R) -> Result<Decoder<R>> {
+        let checksum = match self.checksum {
+            ChecksumMode::Skip if version() < SKIP_CHECKSUMS_VERSION => ChecksumMode::Verify,
+            checksum => checksum,
+        };
         Ok(Decoder {
             r: r,
-            c: try!(DecoderContext::new()),
+            c: try!(self.context()),
             buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
             pos: BUFFER_SIZE,
             len: BUFFER_SIZE,
             // Minimal LZ4 stream size
             next: 11,
+            checksum: checksum,
+            header: 0,
         })
     }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Decoder<R> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(r: R) -> Result<Decoder<R>> {
+        DecoderBuilder::new().build(r)
+    }
 
     /// Immutable reader reference.
     pub fn reader(&self) -> &R {
         &self.r
     }
 
+    /// Content checksum mode this decoder applies, `Verify` when `Skip` was requested from a
+    /// liblz4 older than v1.9.3.
+    pub fn checksum_mode(&self) -> ChecksumMode {
+        self.checksum
+    }
+
     pub fn finish(self) -> (R, Result<()>) {
         (
             self.r,
@@ -51,8 +155,25 @@ impl<R: Read> Decoder<R> {
             },
         )
     }
+
+    fn check_header(&mut self) -> Result<()> {
+        if self.checksum != ChecksumMode::Require || self.header > FLG_OFFSET {
+            return Ok(());
+        }
+        // liblz4 can't consume FLG before it is in the buffer, so it is enough to look at it
+        // as soon as it shows up.
+        let flg = FLG_OFFSET - self.header;
+        if self.pos + flg < self.len && self.buf[self.pos + flg] & FLG_CONTENT_CHECKSUM == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Frame has no content checksum",
+            ));
+        }
+        Ok(())
+    }
 }
 
+#[cfg(feature = "std")]
 impl<R: Read> Read for Decoder<R> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         if self.next == 0 || buf.len() == 0 {
@@ -66,13 +187,22 @@ impl<R: Read> Read for Decoder<R> {
                     false => self.next,
                 };
                 self.len = try!(self.r.read(&mut self.buf[0..need]));
-                if self.len <= 0 {
+                if self.len == 0 {
                     break;
                 }
                 self.pos = 0;
                 self.next -= self.len;
             }
             while (dst_offset < buf.len()) && (self.pos < self.len) {
+                try!(self.check_header());
+                let options = LZ4FDecompressOptions {
+                    stable_dst: 0,
+                    skip_checksums: match self.checksum {
+                        ChecksumMode::Skip => 1,
+                        _ => 0,
+                    },
+                    reserved: [0; 2],
+                };
                 let mut src_size = (self.len - self.pos) as size_t;
                 let mut dst_size = (buf.len() - dst_offset) as size_t;
                 let len = try!(check_error(unsafe {
@@ -82,10 +212,11 @@ impl<R: Read> Read for Decoder<R> {
                         &mut dst_size,
                         self.buf[self.pos..].as_ptr(),
                         &mut src_size,
-                        ptr::null(),
+                        &options,
                     )
                 }));
                 self.pos += src_size as usize;
+                self.header += src_size as usize;
                 dst_offset += dst_size as usize;
                 if len == 0 {
                     self.next = 0;
@@ -100,12 +231,28 @@ impl<R: Read> Read for Decoder<R> {
 }
 
 impl DecoderContext {
-    fn new() -> Result<DecoderContext> {
+    pub(crate) fn new() -> Result<DecoderContext> {
         let mut context = LZ4FDecompressionContext(ptr::null_mut());
         try!(check_error(unsafe {
             LZ4F_createDecompressionContext(&mut context, LZ4F_VERSION)
         }));
-        Ok(DecoderContext { c: context })
+        Ok(DecoderContext {
+            c: context,
+            #[cfg(feature = "allocator")]
+            _allocator: None,
+        })
+    }
+
+    #[cfg(feature = "allocator")]
+    pub(crate) fn with_allocator(allocator: &Allocator) -> Result<DecoderContext> {
+        let custom_mem = CustomMem::new(allocator);
+        let context = try!(check_context(unsafe {
+            LZ4F_createDecompressionContext_advanced(custom_mem.raw(), LZ4F_VERSION)
+        }));
+        Ok(DecoderContext {
+            c: LZ4FDecompressionContext(context),
+            _allocator: Some(custom_mem),
+        })
     }
 }
 
@@ -115,14 +262,15 @@ impl Drop for DecoderContext {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
     extern crate rand;
 
     use self::rand::Rng;
     use self::rand::rngs::StdRng;
     use super::super::encoder::{Encoder, EncoderBuilder};
-    use super::Decoder;
+    use super::super::liblz4::{version, ContentChecksum};
+    use super::{ChecksumMode, Decoder, DecoderBuilder, SKIP_CHECKSUMS_VERSION};
     use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
 
     const BUFFER_SIZE: usize = 64 * 1024;
@@ -289,6 +437,116 @@ mod test {
         finish_decode(decoder);
     }
 
+    #[test]
+    fn test_decoder_checksum_modes() {
+        let expected = b"Some checksummed data".to_vec();
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write(&expected).unwrap();
+        let with_checksum = finish_encode(encoder);
+        let mut encoder = EncoderBuilder::new()
+            .checksum(ContentChecksum::NoChecksum)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write(&expected).unwrap();
+        let without_checksum = finish_encode(encoder);
+
+        for mode in [ChecksumMode::Verify, ChecksumMode::Skip, ChecksumMode::Require].iter() {
+            let mut decoder = DecoderBuilder::new()
+                .checksum(*mode)
+                .build(Cursor::new(with_checksum.clone()))
+                .unwrap();
+            if *mode != ChecksumMode::Skip || version() >= SKIP_CHECKSUMS_VERSION {
+                assert_eq!(decoder.checksum_mode(), *mode);
+            }
+            let mut actual = Vec::new();
+            decoder.read_to_end(&mut actual).unwrap();
+            assert_eq!(expected, actual);
+            finish_decode(decoder);
+        }
+
+        let mut decoder = DecoderBuilder::new()
+            .checksum(ChecksumMode::Require)
+            .build(Cursor::new(without_checksum))
+            .unwrap();
+        let mut actual = Vec::new();
+        let err = decoder.read_to_end(&mut actual).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(actual.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_skip_corrupted_checksum() {
+        let expected = b"Some checksummed data".to_vec();
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write(&expected).unwrap();
+        let (mut encoded, result) = encoder.finish();
+        result.unwrap();
+        // The content checksum is the last field of the frame
+        let len = encoded.len();
+        encoded[len - 1] ^= 0xFF;
+
+        let mut decoder = Decoder::new(Cursor::new(encoded.clone())).unwrap();
+        let mut actual = Vec::new();
+        assert!(decoder.read_to_end(&mut actual).is_err());
+
+        let mut decoder = DecoderBuilder::new()
+            .checksum(ChecksumMode::Skip)
+            .build(Cursor::new(encoded))
+            .unwrap();
+        let mut actual = Vec::new();
+        if version() < SKIP_CHECKSUMS_VERSION {
+            assert_eq!(decoder.checksum_mode(), ChecksumMode::Verify);
+            assert!(decoder.read_to_end(&mut actual).is_err());
+        } else {
+            assert_eq!(decoder.checksum_mode(), ChecksumMode::Skip);
+            decoder.read_to_end(&mut actual).unwrap();
+            assert_eq!(expected, actual);
+        }
+    }
+
+    struct ByteWrapper<R: Read> {
+        r: R,
+    }
+
+    impl<R: Read> Read for ByteWrapper<R> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = buf.len().min(1);
+            self.r.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_decoder_require_checksum_split() {
+        let expected = b"Some data".to_vec();
+        for checksum in [ContentChecksum::ChecksumEnabled, ContentChecksum::NoChecksum].iter() {
+            let mut encoder = EncoderBuilder::new()
+                .checksum(checksum.clone())
+                .build(Vec::new())
+                .unwrap();
+            encoder.write(&expected).unwrap();
+            let encoded = finish_encode(encoder);
+
+            // Header bytes arrive one by one, so FLG shows up in a later read
+            let mut decoder = DecoderBuilder::new()
+                .checksum(ChecksumMode::Require)
+                .build(ByteWrapper {
+                    r: Cursor::new(encoded),
+                })
+                .unwrap();
+            let mut actual = Vec::new();
+            match *checksum {
+                ContentChecksum::ChecksumEnabled => {
+                    decoder.read_to_end(&mut actual).unwrap();
+                    assert_eq!(expected, actual);
+                }
+                ContentChecksum::NoChecksum => {
+                    let err = decoder.read_to_end(&mut actual).unwrap_err();
+                    assert_eq!(err.kind(), ErrorKind::InvalidData);
+                }
+            }
+        }
+    }
+
     fn random() -> StdRng {
         let seed: [u8; 32] = [
             157, 164, 190, 237, 231, 103, 60, 22, 197, 108, 51, 176, 30, 170, 155, 21, 163, 249,
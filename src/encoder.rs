@@ -1,12 +1,80 @@
+#[cfg(all(feature = "std", feature = "allocator"))]
+use super::allocator::{arena, StateBorrow};
+#[cfg(feature = "allocator")]
+use super::allocator::{check_context, Allocator, CustomMem, Measure};
+#[cfg(all(feature = "std", feature = "allocator"))]
+use super::block::StateBuf;
+use super::error::{Error, ErrorKind, Result};
+#[cfg(feature = "allocator")]
+use super::frame::HEADER_SIZE_MAX;
 use super::liblz4::*;
+#[cfg(feature = "allocator")]
+use alloc::sync::Arc;
+use core::cmp;
+use core::ptr;
+use libc::c_int;
+#[cfg(any(feature = "std", feature = "allocator"))]
 use libc::size_t;
-use std::cmp;
-use std::io::Result;
+#[cfg(feature = "std")]
+use std::io::IoSlice;
+#[cfg(feature = "std")]
 use std::io::Write;
-use std::ptr;
 
-struct EncoderContext {
-    c: LZ4FCompressionContext,
+/// Compression level of the frame encoder.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressionLevel {
+    /// Fast mode with the given acceleration: each step trades some compression ratio for more
+    /// speed. `Fast(1)` is the same as `Default`.
+    Fast(u32),
+    /// Fast mode without acceleration.
+    Default,
+    /// High compression, from `LZ4HC_CLEVEL_MIN` to `LZ4HC_CLEVEL_MAX`.
+    HC(u32),
+    /// Highest compression, same as `HC(LZ4HC_CLEVEL_MAX)`.
+    Max,
+}
+
+impl CompressionLevel {
+    /// Value of `LZ4FPreferences::compression_level` for this level.
+    fn raw(self) -> Result<c_int> {
+        match self {
+            // liblz4 uses an acceleration of 1 - level for negative levels
+            CompressionLevel::Fast(acceleration) if acceleration <= c_int::MAX as u32 => {
+                Ok(cmp::min(1 - acceleration as c_int, 0))
+            }
+            CompressionLevel::Default => Ok(0),
+            CompressionLevel::HC(level)
+                if level >= LZ4HC_CLEVEL_MIN as u32 && level <= LZ4HC_CLEVEL_MAX as u32 =>
+            {
+                Ok(level as c_int)
+            }
+            CompressionLevel::Max => Ok(LZ4HC_CLEVEL_MAX),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Compression level {:?} is out of range.", self),
+            )),
+        }
+    }
+}
+
+impl From<i32> for CompressionLevel {
+    /// Converts a level numbered the way liblz4 does: negative levels select the fast mode with
+    /// an acceleration of 1 - level, levels below `LZ4HC_CLEVEL_MIN` the default fast mode and
+    /// higher levels the high compression mode.
+    fn from(level: i32) -> Self {
+        match level {
+            level if level < 0 => CompressionLevel::Fast((1 - level as i64) as u32),
+            level if level < LZ4HC_CLEVEL_MIN => CompressionLevel::Default,
+            level => CompressionLevel::HC(level as u32),
+        }
+    }
+}
+
+pub(crate) struct EncoderContext {
+    pub(crate) c: LZ4FCompressionContext,
+    // Freed after the context, which allocates through it
+    #[cfg(feature = "allocator")]
+    _allocator: Option<CustomMem>,
 }
 
 #[derive(Clone)]
@@ -14,17 +82,27 @@ pub struct EncoderBuilder {
     block_size: BlockSize,
     block_mode: BlockMode,
     checksum: ContentChecksum,
-    // 0 == default (fast mode); values above 16 count as 16; values below 0 count as 0
-    level: u32,
+    level: CompressionLevel,
+    // Only used by levels from LZ4HC_CLEVEL_OPT_MIN
+    favor_dec_speed: bool,
     // 1 == always flush (reduce need for tmp buffer)
     auto_flush: bool,
+    // 0 == unknown; otherwise must match the amount of data written
+    content_size: u64,
+    #[cfg(feature = "allocator")]
+    pub(crate) allocator: Option<Allocator>,
 }
 
-pub struct Encoder<W> {
+/// Frame encoder writing to `W`. `S` holds the borrow of the state buffer of an encoder created
+/// by `with_state()`, which needs the `allocator` feature: without it the frame context is
+/// always allocated by liblz4.
+#[cfg(feature = "std")]
+pub struct Encoder<W, S = ()> {
     c: EncoderContext,
     w: W,
     limit: usize,
     buffer: Vec<u8>,
+    _state: S,
 }
 
 impl EncoderBuilder {
@@ -33,8 +111,12 @@ impl EncoderBuilder {
             block_size: BlockSize::Default,
             block_mode: BlockMode::Linked,
             checksum: ContentChecksum::ChecksumEnabled,
-            level: 0,
+            level: CompressionLevel::Default,
+            favor_dec_speed: false,
             auto_flush: false,
+            content_size: 0,
+            #[cfg(feature = "allocator")]
+            allocator: None,
         }
     }
 
@@ -53,46 +135,156 @@ impl EncoderBuilder {
         self
     }
 
+    /// Sets the level the way liblz4 numbers them: below `LZ4HC_CLEVEL_MIN` is the default fast
+    /// mode, above it is high compression. Levels above `LZ4HC_CLEVEL_MAX` make `build()` fail.
     pub fn level(&mut self, level: u32) -> &mut Self {
+        self.level = match level {
+            level if level < LZ4HC_CLEVEL_MIN as u32 => CompressionLevel::Default,
+            level => CompressionLevel::HC(level),
+        };
+        self
+    }
+
+    /// Sets the level, `build()` fails if it is out of range.
+    pub fn compression_level(&mut self, level: CompressionLevel) -> &mut Self {
         self.level = level;
         self
     }
 
+    /// Makes the high compression parser favor decompression speed over compression ratio. Only
+    /// levels from `LZ4HC_CLEVEL_OPT_MIN` are affected.
+    pub fn favor_dec_speed(&mut self, favor_dec_speed: bool) -> &mut Self {
+        self.favor_dec_speed = favor_dec_speed;
+        self
+    }
+
     pub fn auto_flush(&mut self, auto_flush: bool) -> &mut Self {
         self.auto_flush = auto_flush;
         self
     }
 
-    pub fn build<W: Write>(&self, w: W) -> Result<Encoder<W>> {
-        let block_size = self.block_size.get_size();
-        let preferences = LZ4FPreferences {
+    /// Stores the uncompressed size in the frame header. Finishing the
+    /// encoder fails if a different amount of data was written.
+    pub fn content_size(&mut self, content_size: u64) -> &mut Self {
+        self.content_size = content_size;
+        self
+    }
+
+    /// Allocates the compression contexts through the given allocator instead of the C
+    /// `malloc()`, see the `allocator` module.
+    #[cfg(feature = "allocator")]
+    pub fn allocator(&mut self, allocator: Allocator) -> &mut Self {
+        self.allocator = Some(allocator);
+        self
+    }
+
+    /// Size of the `StateBuf` `Encoder::with_state()` needs with these settings. Found by
+    /// starting a frame on the heap, so this allocates.
+    #[cfg(feature = "allocator")]
+    pub fn state_size(&self) -> Result<usize> {
+        let preferences = try!(self.preferences());
+        let measure = Arc::new(Measure::default());
+        let context = try!(EncoderContext::with_allocator(&(measure.clone() as Allocator)));
+        let mut header = [0u8; HEADER_SIZE_MAX];
+        try!(check_error(unsafe {
+            LZ4F_compressBegin(
+                context.c,
+                header.as_mut_ptr(),
+                header.len() as size_t,
+                &preferences,
+            )
+        }));
+        Ok(measure.total())
+    }
+
+    pub(crate) fn context(&self) -> Result<EncoderContext> {
+        #[cfg(feature = "allocator")]
+        {
+            if let Some(ref allocator) = self.allocator {
+                return EncoderContext::with_allocator(allocator);
+            }
+        }
+        EncoderContext::new()
+    }
+
+    pub(crate) fn preferences(&self) -> Result<LZ4FPreferences> {
+        Ok(LZ4FPreferences {
             frame_info: LZ4FFrameInfo {
                 block_size_id: self.block_size.clone(),
                 block_mode: self.block_mode.clone(),
                 content_checksum_flag: self.checksum.clone(),
-                reserved: [0; 5],
+                frame_type: FrameType::Frame,
+                content_size: self.content_size,
+                dict_id: 0,
+                block_checksum_flag: BlockChecksum::NoBlockChecksum,
             },
-            compression_level: self.level,
+            compression_level: try!(self.level.raw()),
             auto_flush: match self.auto_flush {
                 false => 0,
                 true => 1,
             },
-            reserved: [0; 4],
-        };
+            favor_dec_speed: match self.favor_dec_speed {
+                false => 0,
+                true => 1,
+            },
+            reserved: [0; 3],
+        })
+    }
+
+    #[cfg(feature = "std")]
+    pub fn build<W: Write>(&self, w: W) -> Result<Encoder<W>> {
+        let c = try!(self.context());
+        self.start(c, w, ())
+    }
+
+    /// Writes the frame header with the given context.
+    #[cfg(feature = "std")]
+    fn start<W: Write, S>(&self, c: EncoderContext, w: W, state: S) -> Result<Encoder<W, S>> {
+        let block_size = self.block_size.get_size();
+        let preferences = try!(self.preferences());
         let mut encoder = Encoder {
             w: w,
-            c: try!(EncoderContext::new()),
+            c: c,
             limit: block_size,
             buffer: Vec::with_capacity(try!(check_error(unsafe {
                 LZ4F_compressBound(block_size as size_t, &preferences)
             }))),
+            _state: state,
         };
         try!(encoder.write_header(&preferences));
         Ok(encoder)
     }
 }
 
-impl<W: Write> Encoder<W> {
+#[cfg(all(feature = "std", feature = "allocator"))]
+impl<'a, 'b, W: Write> Encoder<W, StateBorrow<'a, 'b>> {
+    /// Creates an encoder keeping its compression context in the given state buffer instead of
+    /// the heap. The buffer must hold `EncoderBuilder::state_size()` bytes and stays borrowed until
+    /// the encoder is dropped. The output buffer of the encoder is still allocated.
+    ///
+    /// # Errors
+    /// Returns std::io::Error with ErrorKind::Other if the state buffer is too small.
+    pub fn with_state(
+        builder: &EncoderBuilder,
+        w: W,
+        state: &'a mut StateBuf<'b>,
+    ) -> Result<Encoder<W, StateBorrow<'a, 'b>>> {
+        // From here on the buffer is only accessed through this pointer, liblz4 keeps a copy
+        let state: *mut StateBuf<'b> = state;
+        unsafe { (*state).reset() };
+        let context = try!(check_context(unsafe {
+            LZ4F_createCompressionContext_advanced(arena(state), LZ4F_VERSION)
+        }));
+        let c = EncoderContext {
+            c: LZ4FCompressionContext(context),
+            _allocator: None,
+        };
+        builder.start(c, w, StateBorrow::new(state))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write, S> Encoder<W, S> {
     fn write_header(&mut self, preferences: &LZ4FPreferences) -> Result<()> {
         unsafe {
             let len = try!(check_error(LZ4F_compressBegin(
@@ -106,6 +298,31 @@ impl<W: Write> Encoder<W> {
         self.w.write_all(&self.buffer)
     }
 
+    fn write_chunks(
+        &mut self,
+        buffer: &[u8],
+        options: *const LZ4FCompressOptions,
+    ) -> Result<()> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let size = cmp::min(buffer.len() - offset, self.limit);
+            unsafe {
+                let len = try!(check_error(LZ4F_compressUpdate(
+                    self.c.c,
+                    self.buffer.as_mut_ptr(),
+                    self.buffer.capacity() as size_t,
+                    buffer[offset..].as_ptr(),
+                    size as size_t,
+                    options
+                )));
+                self.buffer.set_len(len);
+                try!(self.w.write_all(&self.buffer));
+            }
+            offset += size;
+        }
+        Ok(())
+    }
+
     fn write_end(&mut self) -> Result<()> {
         unsafe {
             let len = try!(check_error(LZ4F_compressEnd(
@@ -131,30 +348,45 @@ impl<W: Write> Encoder<W> {
         let result = self.write_end();
         (self.w, result)
     }
+
+    /// Compresses the whole buffer as the rest of the stream and finishes it,
+    /// like `write_all()` followed by `finish()`.
+    ///
+    /// Because the buffer is guaranteed to stay alive until the frame is
+    /// finished, it is passed to liblz4 with `stable_src` set: in linked block
+    /// mode the previous block is referenced in place as the dictionary
+    /// instead of being copied into the context.
+    pub fn compress_all(mut self, buffer: &[u8]) -> (W, Result<()>) {
+        let options = LZ4FCompressOptions {
+            stable_src: 1,
+            reserved: [0; 3],
+        };
+        let result = match self.write_chunks(buffer, &options) {
+            Ok(()) => self.write_end(),
+            Err(e) => Err(e),
+        };
+        (self.w, result)
+    }
 }
 
-impl<W: Write> Write for Encoder<W> {
+#[cfg(feature = "std")]
+impl<W: Write, S> Write for Encoder<W, S> {
     fn write(&mut self, buffer: &[u8]) -> Result<usize> {
-        let mut offset = 0;
-        while offset < buffer.len() {
-            let size = cmp::min(buffer.len() - offset, self.limit);
-            unsafe {
-                let len = try!(check_error(LZ4F_compressUpdate(
-                    self.c.c,
-                    self.buffer.as_mut_ptr(),
-                    self.buffer.capacity() as size_t,
-                    buffer[offset..].as_ptr(),
-                    size as size_t,
-                    ptr::null()
-                )));
-                self.buffer.set_len(len);
-                try!(self.w.write_all(&self.buffer));
-            }
-            offset += size;
-        }
+        try!(self.write_chunks(buffer, ptr::null()));
         Ok(buffer.len())
     }
 
+    fn write_vectored(&mut self, buffers: &[IoSlice]) -> Result<usize> {
+        // Every slice is fed to liblz4 as is: the context accumulates partial
+        // blocks itself, so there is no need to coalesce them here.
+        let mut total = 0;
+        for buffer in buffers {
+            try!(self.write_chunks(buffer, ptr::null()));
+            total += buffer.len();
+        }
+        Ok(total)
+    }
+
     fn flush(&mut self) -> Result<()> {
         loop {
             unsafe {
@@ -176,12 +408,28 @@ impl<W: Write> Write for Encoder<W> {
 }
 
 impl EncoderContext {
-    fn new() -> Result<EncoderContext> {
+    pub(crate) fn new() -> Result<EncoderContext> {
         let mut context = LZ4FCompressionContext(ptr::null_mut());
         try!(check_error(unsafe {
             LZ4F_createCompressionContext(&mut context, LZ4F_VERSION)
         }));
-        Ok(EncoderContext { c: context })
+        Ok(EncoderContext {
+            c: context,
+            #[cfg(feature = "allocator")]
+            _allocator: None,
+        })
+    }
+
+    #[cfg(feature = "allocator")]
+    fn with_allocator(allocator: &Allocator) -> Result<EncoderContext> {
+        let custom_mem = CustomMem::new(allocator);
+        let context = try!(check_context(unsafe {
+            LZ4F_createCompressionContext_advanced(custom_mem.raw(), LZ4F_VERSION)
+        }));
+        Ok(EncoderContext {
+            c: LZ4FCompressionContext(context),
+            _allocator: Some(custom_mem),
+        })
     }
 }
 
@@ -191,10 +439,20 @@ impl Drop for EncoderContext {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use super::EncoderBuilder;
-    use std::io::Write;
+    use super::super::decoder::Decoder;
+    use super::{CompressionLevel, EncoderBuilder};
+    use std::io::{ErrorKind, IoSlice, Read, Write};
+
+    fn decode(compressed: &[u8]) -> Vec<u8> {
+        let mut actual = Vec::new();
+        Decoder::new(compressed)
+            .unwrap()
+            .read_to_end(&mut actual)
+            .unwrap();
+        actual
+    }
 
     #[test]
     fn test_encoder_smoke() {
@@ -219,10 +477,180 @@ mod test {
         result.unwrap();
     }
 
+    #[test]
+    fn test_encoder_vectored() {
+        let mut encoder = EncoderBuilder::new().level(1).build(Vec::new()).unwrap();
+        let buffers = [
+            IoSlice::new(b"Some "),
+            IoSlice::new(b""),
+            IoSlice::new(b"vectored data"),
+        ];
+        assert_eq!(encoder.write_vectored(&buffers).unwrap(), 18);
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+
+        let actual = decode(&compressed);
+        assert_eq!(&actual[..], &b"Some vectored data"[..]);
+    }
+
+    #[test]
+    fn test_encoder_compress_all() {
+        let mut expected = Vec::new();
+        for i in 0..1024 * 1024 {
+            expected.push((i % 251) as u8);
+        }
+        let mut encoder = EncoderBuilder::new().level(1).build(Vec::new()).unwrap();
+        encoder.write_all(b"prefix").unwrap();
+        let (compressed, result) = encoder.compress_all(&expected);
+        result.unwrap();
+
+        let actual = decode(&compressed);
+        assert_eq!(&actual[..6], &b"prefix"[..]);
+        assert_eq!(&actual[6..], &expected[..]);
+    }
+
+    #[test]
+    fn test_encoder_content_size() {
+        let mut encoder = EncoderBuilder::new()
+            .content_size(9)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write(b"Some data").unwrap();
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+        // FLG has the content size bit set and the size follows BD
+        assert_eq!(compressed[4] & 0x08, 0x08);
+        assert_eq!(&compressed[6..14], &[9, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut encoder = EncoderBuilder::new()
+            .content_size(10)
+            .build(Vec::new())
+            .unwrap();
+        encoder.write(b"Some data").unwrap();
+        let (_, result) = encoder.finish();
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_encoder_send() {
         fn check_send<S: Send>(_: &S) {}
         let enc = EncoderBuilder::new().build(Vec::new());
         check_send(&enc);
     }
+
+    #[test]
+    fn test_encoder_compression_level() {
+        let mut expected = Vec::new();
+        for i in 0..256 * 1024 {
+            expected.push((i % 251) as u8 ^ (i / 1000) as u8);
+        }
+        let levels = [
+            CompressionLevel::Fast(1),
+            CompressionLevel::Fast(50),
+            CompressionLevel::Default,
+            CompressionLevel::HC(3),
+            CompressionLevel::HC(9),
+            CompressionLevel::Max,
+        ];
+        for level in levels.iter() {
+            for favor_dec_speed in [false, true].iter() {
+                let mut encoder = EncoderBuilder::new()
+                    .compression_level(*level)
+                    .favor_dec_speed(*favor_dec_speed)
+                    .build(Vec::new())
+                    .unwrap();
+                encoder.write_all(&expected).unwrap();
+                let (compressed, result) = encoder.finish();
+                result.unwrap();
+                assert_eq!(decode(&compressed), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoder_compression_level_range() {
+        let levels = [
+            CompressionLevel::HC(2),
+            CompressionLevel::HC(13),
+            CompressionLevel::Fast(u32::MAX),
+        ];
+        for level in levels.iter() {
+            let err = EncoderBuilder::new()
+                .compression_level(*level)
+                .build(Vec::new())
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        let err = EncoderBuilder::new()
+            .level(16)
+            .build(Vec::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // Raw levels below LZ4HC_CLEVEL_MIN are the fast mode
+        assert!(EncoderBuilder::new().level(2).build(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_encoder_acceleration() {
+        let mut expected = Vec::new();
+        for i in 0..1024 * 1024 {
+            expected.push((i % 251) as u8 ^ (i / 777) as u8 ^ (i / 5) as u8);
+        }
+        let mut sizes = Vec::new();
+        for level in [0, -10, -1000].iter() {
+            let mut encoder = EncoderBuilder::new()
+                .compression_level(CompressionLevel::from(*level))
+                .build(Vec::new())
+                .unwrap();
+            encoder.write_all(&expected).unwrap();
+            let (compressed, result) = encoder.finish();
+            result.unwrap();
+            assert_eq!(decode(&compressed), expected);
+            sizes.push(compressed.len());
+        }
+        // Acceleration is passed down to liblz4, trading ratio for speed
+        assert!(sizes[0] < sizes[1]);
+        assert!(sizes[1] < sizes[2]);
+    }
+
+    #[test]
+    fn test_compression_level_from_raw() {
+        assert_eq!(CompressionLevel::from(-1), CompressionLevel::Fast(2));
+        assert_eq!(CompressionLevel::from(0), CompressionLevel::Default);
+        assert_eq!(CompressionLevel::from(2), CompressionLevel::Default);
+        assert_eq!(CompressionLevel::from(3), CompressionLevel::HC(3));
+        assert_eq!(
+            CompressionLevel::from(i32::MIN),
+            CompressionLevel::Fast(1 << 31 | 1)
+        );
+        assert_eq!(CompressionLevel::Fast(2).raw().unwrap(), -1);
+        assert!(CompressionLevel::from(i32::MIN).raw().is_err());
+    }
+
+    #[cfg(feature = "allocator")]
+    #[test]
+    fn test_with_state() {
+        use super::super::block::StateBuf;
+        use super::Encoder;
+
+        let expected: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+        for builder in [EncoderBuilder::new(), EncoderBuilder::new().level(9).clone()].iter() {
+            let mut memory = vec![0u8; builder.state_size().unwrap()];
+            let mut state = StateBuf::new(&mut memory);
+            for _ in 0..2 {
+                let mut encoder = Encoder::with_state(builder, Vec::new(), &mut state).unwrap();
+                encoder.write_all(&expected).unwrap();
+                let (compressed, result) = encoder.finish();
+                result.unwrap();
+                assert_eq!(decode(&compressed), expected);
+            }
+        }
+
+        let builder = EncoderBuilder::new().level(9).clone();
+        let mut memory = vec![0u8; EncoderBuilder::new().state_size().unwrap()];
+        let mut state = StateBuf::new(&mut memory);
+        assert!(Encoder::with_state(&builder, Vec::new(), &mut state).is_err());
+    }
 }
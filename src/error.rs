@@ -0,0 +1,60 @@
+//! Error type of the crate. With the `std` feature (default) this is `std::io::Error`, so the
+//! one-shot helpers compose with the `Read`/`Write` based API. Without it, a minimal stand-in
+//! with the same constructor and kinds is used.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+pub use self::core_error::{Error, ErrorKind, Result};
+
+#[cfg(not(feature = "std"))]
+mod core_error {
+    use alloc::borrow::Cow;
+    use core::fmt;
+    use core::result;
+
+    /// The subset of `std::io::ErrorKind` this crate reports.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum ErrorKind {
+        /// A parameter was incorrect, e.g. a compression level out of range.
+        InvalidInput,
+        /// The compressed data is malformed.
+        InvalidData,
+        /// The compressed data ends in the middle of a frame.
+        UnexpectedEof,
+        /// The output does not fit into the destination buffer.
+        WriteZero,
+        /// liblz4 reported an error.
+        Other,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: Cow<'static, str>,
+    }
+
+    pub type Result<T> = result::Result<T, Error>;
+
+    impl Error {
+        pub fn new<M: Into<Cow<'static, str>>>(kind: ErrorKind, message: M) -> Error {
+            Error {
+                kind,
+                message: message.into(),
+            }
+        }
+
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.message)
+        }
+    }
+
+    impl core::error::Error for Error {}
+}
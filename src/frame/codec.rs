@@ -0,0 +1,255 @@
+#[cfg(feature = "allocator")]
+use super::super::allocator::Allocator;
+use super::super::decoder::DecoderContext;
+use super::super::encoder::{EncoderBuilder, EncoderContext};
+use super::super::error::{Error, ErrorKind, Result};
+use super::super::liblz4::*;
+use core::ptr;
+use libc::size_t;
+
+/// Largest frame header `FrameEncoder::begin()` writes.
+pub const HEADER_SIZE_MAX: usize = 19;
+
+/// Frame compression without any I/O: every call writes into a caller provided slice and returns
+/// the number of bytes written. The output of `begin()`, the `update()` calls and `end()` makes up
+/// one frame.
+///
+/// # Examples
+/// ```
+/// use lz4::EncoderBuilder;
+/// use lz4::frame::{decompress, FrameEncoder, HEADER_SIZE_MAX};
+///
+/// let src = vec![7u8; 1024];
+/// let mut encoder = FrameEncoder::new(&EncoderBuilder::new()).unwrap();
+/// let mut dst = vec![0u8; HEADER_SIZE_MAX + encoder.compress_bound(src.len()).unwrap()];
+/// let mut len = encoder.begin(&mut dst).unwrap();
+/// len += encoder.update(&src, &mut dst[len..]).unwrap();
+/// len += encoder.end(&mut dst[len..]).unwrap();
+///
+/// assert_eq!(decompress(&dst[..len]).unwrap(), src);
+/// ```
+pub struct FrameEncoder {
+    c: EncoderContext,
+    preferences: LZ4FPreferences,
+}
+
+impl FrameEncoder {
+    /// Creates an encoder with the settings of the given builder.
+    ///
+    /// # Errors
+    /// Returns std::io::Error with ErrorKind::InvalidInput if the builder compression level is out
+    /// of range.
+    pub fn new(builder: &EncoderBuilder) -> Result<FrameEncoder> {
+        Ok(FrameEncoder {
+            preferences: try!(builder.preferences()),
+            c: try!(builder.context()),
+        })
+    }
+
+    /// Returns the destination size `update()` needs for src_size bytes of input, including
+    /// whatever is still buffered from previous calls. Also large enough for `flush()` and
+    /// `end()`.
+    pub fn compress_bound(&self, src_size: usize) -> Result<usize> {
+        check_error(unsafe { LZ4F_compressBound(src_size as size_t, &self.preferences) })
+    }
+
+    /// Starts a new frame by writing its header, `HEADER_SIZE_MAX` bytes are always enough. Also
+    /// used to start the next frame after `end()`.
+    pub fn begin(&mut self, dst: &mut [u8]) -> Result<usize> {
+        if dst.len() < HEADER_SIZE_MAX {
+            return Err(buffer_too_small());
+        }
+        check_error(unsafe {
+            LZ4F_compressBegin(
+                self.c.c,
+                dst.as_mut_ptr(),
+                dst.len() as size_t,
+                &self.preferences,
+            )
+        })
+    }
+
+    /// Compresses src. Unless auto flush is enabled, data may be kept back until a block is full.
+    pub fn update(&mut self, src: &[u8], dst: &mut [u8]) -> Result<usize> {
+        if dst.len() < try!(self.compress_bound(src.len())) {
+            return Err(buffer_too_small());
+        }
+        check_error(unsafe {
+            LZ4F_compressUpdate(
+                self.c.c,
+                dst.as_mut_ptr(),
+                dst.len() as size_t,
+                src.as_ptr(),
+                src.len() as size_t,
+                ptr::null(),
+            )
+        })
+    }
+
+    /// Writes out the data kept back by `update()`.
+    pub fn flush(&mut self, dst: &mut [u8]) -> Result<usize> {
+        if dst.len() < try!(self.compress_bound(0)) {
+            return Err(buffer_too_small());
+        }
+        check_error(unsafe {
+            LZ4F_flush(self.c.c, dst.as_mut_ptr(), dst.len() as size_t, ptr::null())
+        })
+    }
+
+    /// Writes out the data kept back by `update()` and finishes the frame.
+    pub fn end(&mut self, dst: &mut [u8]) -> Result<usize> {
+        if dst.len() < try!(self.compress_bound(0)) {
+            return Err(buffer_too_small());
+        }
+        check_error(unsafe {
+            LZ4F_compressEnd(self.c.c, dst.as_mut_ptr(), dst.len() as size_t, ptr::null())
+        })
+    }
+}
+
+/// Frame decompression without any I/O: input and output are passed as slices, in pieces of any
+/// size. Consecutive frames are decoded one after the other.
+///
+/// # Examples
+/// ```
+/// use lz4::EncoderBuilder;
+/// use lz4::frame::{compress, FrameDecoder};
+///
+/// let compressed = compress(b"some data", &EncoderBuilder::new()).unwrap();
+/// let mut decoder = FrameDecoder::new().unwrap();
+/// let mut dst = [0u8; 64];
+/// let (consumed, written, next) = decoder.decompress(&compressed, &mut dst).unwrap();
+///
+/// assert_eq!((consumed, next), (compressed.len(), 0));
+/// assert_eq!(&dst[..written], b"some data");
+/// ```
+pub struct FrameDecoder {
+    c: DecoderContext,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Result<FrameDecoder> {
+        Ok(FrameDecoder {
+            c: try!(DecoderContext::new()),
+        })
+    }
+
+    /// Creates a decoder allocating through the given allocator instead of the C `malloc()`, see
+    /// the `allocator` module.
+    #[cfg(feature = "allocator")]
+    pub fn with_allocator(allocator: &Allocator) -> Result<FrameDecoder> {
+        Ok(FrameDecoder {
+            c: try!(DecoderContext::with_allocator(allocator)),
+        })
+    }
+
+    /// Decompresses as much of src into dst as possible. Returns the number of bytes consumed
+    /// from src, the number of bytes written to dst and a hint for the size of the next src
+    /// piece. A hint of 0 means the frame is complete: the next call starts a new frame.
+    ///
+    /// # Errors
+    /// Returns std::io::Error with ErrorKind::Other if the decompression failed inside the C
+    /// library. The decoder must be `reset()` before it can be used again.
+    pub fn decompress(&mut self, src: &[u8], dst: &mut [u8]) -> Result<(usize, usize, usize)> {
+        let mut src_size = src.len() as size_t;
+        let mut dst_size = dst.len() as size_t;
+        let next = try!(check_error(unsafe {
+            LZ4F_decompress(
+                self.c.c,
+                dst.as_mut_ptr(),
+                &mut dst_size,
+                src.as_ptr(),
+                &mut src_size,
+                ptr::null(),
+            )
+        }));
+        Ok((src_size as usize, dst_size as usize, next))
+    }
+
+    /// Drops the frame in progress, the next call starts a new frame.
+    pub fn reset(&mut self) {
+        unsafe { LZ4F_resetDecompressionContext(self.c.c) };
+    }
+}
+
+pub(super) fn buffer_too_small() -> Error {
+    Error::new(ErrorKind::WriteZero, "Destination buffer is too small.")
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+    use super::super::super::encoder::EncoderBuilder;
+    use super::{FrameDecoder, FrameEncoder, HEADER_SIZE_MAX};
+    use error::ErrorKind;
+    use frame::{compress, decompress, sample};
+
+    #[test]
+    fn test_encoder_pieces() {
+        let expected = sample(300 * 1024 + 5);
+        let mut encoder = FrameEncoder::new(EncoderBuilder::new().level(4)).unwrap();
+        let mut compressed = vec![0u8; HEADER_SIZE_MAX];
+        let len = encoder.begin(&mut compressed).unwrap();
+        compressed.truncate(len);
+        for chunk in expected.chunks(10000) {
+            let mut dst = vec![0u8; encoder.compress_bound(chunk.len()).unwrap()];
+            let len = encoder.update(chunk, &mut dst).unwrap();
+            compressed.extend_from_slice(&dst[..len]);
+        }
+        let mut dst = vec![0u8; encoder.compress_bound(0).unwrap()];
+        let len = encoder.end(&mut dst).unwrap();
+        compressed.extend_from_slice(&dst[..len]);
+
+        assert_eq!(decompress(&compressed).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_encoder_buffer_too_small() {
+        let mut encoder = FrameEncoder::new(&EncoderBuilder::new()).unwrap();
+        let mut dst = [0u8; HEADER_SIZE_MAX];
+        let err = encoder.begin(&mut dst[..4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        encoder.begin(&mut dst).unwrap();
+        let err = encoder.update(&[0u8; 100], &mut dst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+    }
+
+    #[test]
+    fn test_decoder_pieces() {
+        let expected = sample(200 * 1024);
+        let mut compressed = compress(&expected, &EncoderBuilder::new()).unwrap();
+        compressed.extend(compress(&expected[..1000], &EncoderBuilder::new()).unwrap());
+
+        let mut decoder = FrameDecoder::new().unwrap();
+        let mut actual = Vec::new();
+        let mut dst = [0u8; 4096];
+        let mut offset = 0;
+        let mut frames = 0;
+        while offset < compressed.len() {
+            let end = (offset + 777).min(compressed.len());
+            let (consumed, written, next) =
+                decoder.decompress(&compressed[offset..end], &mut dst).unwrap();
+            offset += consumed;
+            actual.extend_from_slice(&dst[..written]);
+            if next == 0 {
+                frames += 1;
+            }
+        }
+        assert_eq!(frames, 2);
+        assert_eq!(&actual[..expected.len()], &expected[..]);
+        assert_eq!(&actual[expected.len()..], &expected[..1000]);
+    }
+
+    #[test]
+    fn test_decoder_reset() {
+        let expected = sample(1000);
+        let compressed = compress(&expected, &EncoderBuilder::new()).unwrap();
+        let mut decoder = FrameDecoder::new().unwrap();
+        let mut dst = vec![0u8; expected.len()];
+        decoder.decompress(&compressed[..10], &mut dst).unwrap();
+        decoder.reset();
+        let (consumed, written, next) = decoder.decompress(&compressed, &mut dst).unwrap();
+        assert_eq!((consumed, written, next), (compressed.len(), expected.len(), 0));
+        assert_eq!(dst, expected);
+    }
+}
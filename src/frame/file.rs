@@ -0,0 +1,335 @@
+use super::super::decoder::{Decoder, DecoderContext};
+use super::super::encoder::EncoderBuilder;
+use super::super::liblz4::*;
+use super::{check_content_size, scan, FrameReport};
+use libc::size_t;
+use memmap2::{Mmap, MmapMut};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+/// Compresses the src file into a single frame written to dst, which is created or truncated.
+/// The input is memory-mapped and handed to liblz4 as a whole with `stable_src` set, so it is
+/// never copied. The source length is stored in the frame header as content size.
+///
+/// The src file must not be modified, by this or another process, while it is being compressed:
+/// the mapping would change under liblz4, which is undefined behaviour.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if src and dst are the same file.
+/// Returns std::io::Error from opening, mapping or writing the files.
+/// Returns std::io::Error with ErrorKind::Other if the compression failed inside the C library.
+///
+pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    builder: &EncoderBuilder,
+) -> Result<()> {
+    try!(check_distinct(src.as_ref(), dst.as_ref()));
+    let input = try!(map(&try!(File::open(src))));
+    let input = input.as_ref().map_or(&[][..], |m| &m[..]);
+    let mut builder = builder.clone();
+    builder.content_size(input.len() as u64);
+    let encoder = try!(builder.build(try!(File::create(dst))));
+    let (_, result) = encoder.compress_all(input);
+    result
+}
+
+/// Decompresses all frames of the src file into dst, which is created or truncated. The input is
+/// memory-mapped. When every frame carries its content size, dst is sized up front and mapped
+/// too, letting liblz4 decode straight into it; otherwise the output is written as it is decoded.
+/// dst is removed if decompression fails.
+///
+/// The src file must not be modified, by this or another process, while it is being
+/// decompressed: the mapping would change under liblz4, which is undefined behaviour.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if src and dst are the same file.
+/// Returns std::io::Error from opening, mapping or writing the files.
+/// Returns std::io::Error with ErrorKind::UnexpectedEof if the input ends in the middle of a
+/// frame.
+/// Returns std::io::Error with ErrorKind::InvalidData if the input is not made of LZ4 frames or a
+/// frame does not match the content size in its header.
+/// Returns std::io::Error with ErrorKind::Other if the decompression failed inside the C library.
+///
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> Result<()> {
+    try!(check_distinct(src.as_ref(), dst.as_ref()));
+    let input = try!(map(&try!(File::open(src))));
+    let input = input.as_ref().map_or(&[][..], |m| &m[..]);
+    let report = try!(scan(input));
+    for frame in report.frames.iter() {
+        if let Some(content_size) = frame.content_size {
+            try!(check_content_size(content_size, frame.compressed_size));
+        }
+    }
+
+    let output = try!(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&dst));
+    let result = decompress_report(input, &report, output);
+    if result.is_err() {
+        let _ = fs::remove_file(&dst);
+    }
+    result
+}
+
+fn decompress_report(input: &[u8], report: &FrameReport, output: File) -> Result<()> {
+    let sized = report
+        .frames
+        .iter()
+        .all(|f| f.content_size.is_some() || f.blocks == 0);
+    if !sized {
+        return decompress_stream(input, output);
+    }
+    try!(output.set_len(report.uncompressed_size));
+    if report.uncompressed_size == 0 {
+        return decompress_into(input, &mut []);
+    }
+    let mut map = unsafe { try!(MmapMut::map_mut(&output)) };
+    try!(decompress_into(input, &mut map));
+    map.flush()
+}
+
+/// Fails if dst is the src file, which truncating dst would pull from under its mapping.
+fn check_distinct(src: &Path, dst: &Path) -> Result<()> {
+    if !dst.exists() {
+        return Ok(());
+    }
+    if try!(same_file(src, dst)) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Source and destination are the same file.",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (try!(fs::metadata(a)), try!(fs::metadata(b)));
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> Result<bool> {
+    Ok(try!(fs::canonicalize(a)) == try!(fs::canonicalize(b)))
+}
+
+/// Maps the whole file, or returns None for an empty one, which can't be mapped.
+fn map(file: &File) -> Result<Option<Mmap>> {
+    if try!(file.metadata()).len() == 0 {
+        return Ok(None);
+    }
+    // The mapping is only read while the file stays open here; other processes changing it in
+    // the meantime is outside of what this helper can guard against.
+    Ok(Some(unsafe { try!(Mmap::map(file)) }))
+}
+
+/// Decodes all frames of src, which must fill dst exactly.
+fn decompress_into(src: &[u8], dst: &mut [u8]) -> Result<()> {
+    let context = try!(DecoderContext::new());
+    // The output does not move between calls, so liblz4 uses it as the dictionary in place
+    let options = LZ4FDecompressOptions {
+        stable_dst: 1,
+        skip_checksums: 0,
+        reserved: [0; 2],
+    };
+    let mut src_offset = 0;
+    let mut dst_offset = 0;
+    let mut next = 0;
+    while src_offset < src.len() {
+        let mut src_size = (src.len() - src_offset) as size_t;
+        let mut dst_size = (dst.len() - dst_offset) as size_t;
+        next = try!(check_error(unsafe {
+            LZ4F_decompress(
+                context.c,
+                dst[dst_offset..].as_mut_ptr(),
+                &mut dst_size,
+                src[src_offset..].as_ptr(),
+                &mut src_size,
+                &options,
+            )
+        }));
+        src_offset += src_size as usize;
+        dst_offset += dst_size as usize;
+        if src_size == 0 && dst_size == 0 {
+            // Output is full but liblz4 still has data to produce
+            break;
+        }
+    }
+    if next != 0 && src_offset == src.len() {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Source file ends in the middle of a frame.",
+        ));
+    }
+    if src_offset != src.len() || dst_offset != dst.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Frame content does not match the content size in its header.",
+        ));
+    }
+    Ok(())
+}
+
+fn decompress_stream(mut src: &[u8], dst: File) -> Result<()> {
+    let mut dst = BufWriter::new(dst);
+    // Decoder never reads past the end of a frame, each frame gets its own
+    while !src.is_empty() {
+        let mut decoder = try!(Decoder::new(src));
+        try!(io::copy(&mut decoder, &mut dst));
+        let (rest, result) = decoder.finish();
+        try!(result);
+        src = rest;
+    }
+    dst.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::encoder::EncoderBuilder;
+    use super::super::super::liblz4::BlockSize;
+    use super::super::decompress;
+    use super::{compress_file, decompress_file};
+    use frame::sample;
+    use std::env;
+    use std::fs;
+    use std::io::{ErrorKind, Write};
+    use std::path::PathBuf;
+    use std::process;
+    use xxhash::xxh32;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("lz4-rs-{}-{}", process::id(), name))
+    }
+
+    fn roundtrip(name: &str, data: &[u8]) {
+        let src = temp_path(name);
+        let compressed = temp_path(&format!("{}.lz4", name));
+        let dst = temp_path(&format!("{}.out", name));
+        fs::write(&src, data).unwrap();
+        let mut builder = EncoderBuilder::new();
+        builder.block_size(BlockSize::Max64KB);
+        compress_file(&src, &compressed, &builder).unwrap();
+        assert_eq!(decompress(&fs::read(&compressed).unwrap()).unwrap(), data);
+        decompress_file(&compressed, &dst).unwrap();
+        assert_eq!(fs::read(&dst).unwrap(), data);
+        for path in [src, compressed, dst].iter() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_file_roundtrip() {
+        roundtrip("roundtrip", &sample(3 * 1024 * 1024 + 17));
+        roundtrip("empty", &[]);
+    }
+
+    #[test]
+    fn test_file_without_content_size() {
+        let data = sample(1024 * 1024);
+        let mut compressed = Vec::new();
+        for _ in 0..2 {
+            let mut encoder = EncoderBuilder::new().build(compressed).unwrap();
+            encoder.write_all(&data).unwrap();
+            let (w, result) = encoder.finish();
+            result.unwrap();
+            compressed = w;
+        }
+        let src = temp_path("stream.lz4");
+        let dst = temp_path("stream.out");
+        fs::write(&src, &compressed).unwrap();
+        decompress_file(&src, &dst).unwrap();
+        let actual = fs::read(&dst).unwrap();
+        assert_eq!(&actual[..data.len()], &data[..]);
+        assert_eq!(&actual[data.len()..], &data[..]);
+        fs::remove_file(&src).unwrap();
+        fs::remove_file(&dst).unwrap();
+    }
+
+    #[test]
+    fn test_file_truncated() {
+        let data = sample(100 * 1024);
+        let src = temp_path("truncated");
+        let compressed = temp_path("truncated.lz4");
+        let dst = temp_path("truncated.out");
+        fs::write(&src, &data).unwrap();
+        compress_file(&src, &compressed, &EncoderBuilder::new()).unwrap();
+        let mut bytes = fs::read(&compressed).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&compressed, &bytes).unwrap();
+        let err = decompress_file(&compressed, &dst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        // Input is checked before the output is created
+        assert!(!dst.exists());
+        fs::remove_file(&src).unwrap();
+        fs::remove_file(&compressed).unwrap();
+    }
+
+    #[test]
+    fn test_file_content_size_too_large() {
+        // Empty frame claiming 1TB of content
+        let mut frame = vec![0x04, 0x22, 0x4D, 0x18, 0x48, 0x40];
+        frame.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        let checksum = (xxh32(&frame[4..], 0) >> 8) as u8;
+        frame.push(checksum);
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        let src = temp_path("oversized.lz4");
+        let dst = temp_path("oversized.out");
+        fs::write(&src, &frame).unwrap();
+        let err = decompress_file(&src, &dst).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(!dst.exists());
+        fs::remove_file(&src).unwrap();
+    }
+
+    #[test]
+    fn test_file_same_path() {
+        let data = sample(100 * 1024);
+        let src = temp_path("same");
+        let compressed = temp_path("same.lz4");
+        fs::write(&src, &data).unwrap();
+        let err = compress_file(&src, &src, &EncoderBuilder::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&src).unwrap(), data);
+
+        compress_file(&src, &compressed, &EncoderBuilder::new()).unwrap();
+        let bytes = fs::read(&compressed).unwrap();
+        let err = decompress_file(&compressed, &compressed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&compressed).unwrap(), bytes);
+
+        // Another name for the same file
+        let link = temp_path("same.link");
+        fs::hard_link(&compressed, &link).unwrap();
+        let err = decompress_file(&compressed, &link).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(fs::read(&compressed).unwrap(), bytes);
+        for path in [src, compressed, link].iter() {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_file_corrupted_removes_output() {
+        let data = sample(100 * 1024);
+        let src = temp_path("corrupted");
+        let compressed = temp_path("corrupted.lz4");
+        let dst = temp_path("corrupted.out");
+        fs::write(&src, &data).unwrap();
+        compress_file(&src, &compressed, &EncoderBuilder::new()).unwrap();
+        // Block data is only checked while decoding, after the output is created
+        let mut bytes = fs::read(&compressed).unwrap();
+        let len = bytes.len();
+        bytes[len / 2] ^= 0xFF;
+        fs::write(&compressed, &bytes).unwrap();
+        assert!(decompress_file(&compressed, &dst).is_err());
+        assert!(!dst.exists());
+        fs::remove_file(&src).unwrap();
+        fs::remove_file(&compressed).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+use super::super::decoder::DecoderContext;
+use super::decompress;
+use super::super::seekable::seek_position;
+use super::verify::{le32, read_magic, scan_frame, MAGIC, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK};
+use std::cmp;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+
+/// Location of a single frame, in the stream and in the uncompressed data.
+#[derive(Clone, Debug)]
+pub struct FrameEntry {
+    /// Position of the frame magic number in the stream.
+    pub compressed_offset: u64,
+    pub compressed_size: u64,
+    /// Position of the frame content in the uncompressed data.
+    pub uncompressed_offset: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Offset table of a stream made of concatenated frames, built from the frame headers alone.
+/// Every frame must carry its content size, as the blocks are not decoded.
+#[derive(Clone, Debug, Default)]
+pub struct FrameIndex {
+    frames: Vec<FrameEntry>,
+    content_size: u64,
+}
+
+impl FrameIndex {
+    /// Reads the frame headers and block size fields from the current position to the end of the
+    /// stream, seeking over block data and skippable frames.
+    ///
+    /// # Errors
+    /// Returns std::io::Error with ErrorKind::UnexpectedEof if the stream ends in the middle of a
+    /// frame.
+    /// Returns std::io::Error with ErrorKind::InvalidData if the stream is not made of LZ4 frames
+    /// or a frame without blocks has no content size.
+    ///
+    pub fn build<R: Read + Seek>(mut r: R) -> Result<FrameIndex> {
+        let context = try!(DecoderContext::new());
+        let mut index = FrameIndex::default();
+        let mut offset = try!(r.stream_position());
+        loop {
+            let mut magic = [0u8; 4];
+            if !try!(read_magic(&mut r, &mut magic)) {
+                break;
+            }
+            match le32(&magic) {
+                MAGIC => {
+                    let frame = try!(scan_frame(&context, &mut r, &magic, |r, len| {
+                        r.seek(SeekFrom::Current(len as i64)).map(|_| ())
+                    }));
+
+                    let uncompressed_size = match frame.content_size {
+                        Some(size) => size,
+                        None if frame.blocks == 0 => 0,
+                        None => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "Frame has no content size, it can't be indexed",
+                            ))
+                        }
+                    };
+                    index.frames.push(FrameEntry {
+                        compressed_offset: offset,
+                        compressed_size: frame.compressed_size,
+                        uncompressed_offset: index.content_size,
+                        uncompressed_size,
+                    });
+                    index.content_size = match index.content_size.checked_add(uncompressed_size) {
+                        Some(size) => size,
+                        None => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "Frame content sizes add up beyond 2^64 bytes",
+                            ))
+                        }
+                    };
+                    offset += frame.compressed_size;
+                }
+                m if m & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC => {
+                    let mut size = [0u8; 4];
+                    try!(r.read_exact(&mut size));
+                    let size = le32(&size) as u64;
+                    try!(r.seek(SeekFrom::Current(size as i64)));
+                    offset += 8 + size;
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Unrecognized frame magic number",
+                    ))
+                }
+            }
+        }
+        // Seeking over the last bytes does not notice a truncated stream
+        if offset > try!(r.seek(SeekFrom::End(0))) {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Stream ends in the middle of a frame",
+            ));
+        }
+        Ok(index)
+    }
+
+    pub fn frames(&self) -> &[FrameEntry] {
+        &self.frames
+    }
+
+    /// Total size of the uncompressed data.
+    pub fn content_size(&self) -> u64 {
+        self.content_size
+    }
+
+    /// Returns the frame holding the byte at the given uncompressed offset.
+    pub fn find(&self, offset: u64) -> Option<&FrameEntry> {
+        self.position(offset).map(|i| &self.frames[i])
+    }
+
+    fn position(&self, offset: u64) -> Option<usize> {
+        let i = self
+            .frames
+            .partition_point(|f| f.uncompressed_offset + f.uncompressed_size <= offset);
+        match i < self.frames.len() {
+            true => Some(i),
+            false => None,
+        }
+    }
+}
+
+/// Reader providing random access to a stream of concatenated frames through `Read` and `Seek`.
+/// The frame covering the current position is decoded as a whole and kept in memory, so this
+/// suits streams made of many small frames.
+pub struct FrameReader<R> {
+    r: R,
+    index: FrameIndex,
+    pos: u64,
+    // Index of the frame held in `frame`
+    current: Option<usize>,
+    frame: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl<R: Read + Seek> FrameReader<R> {
+    /// Builds the frame index of the stream, from its current position.
+    pub fn new(mut r: R) -> Result<FrameReader<R>> {
+        let index = try!(FrameIndex::build(&mut r));
+        Ok(FrameReader::with_index(r, index))
+    }
+
+    /// Uses an index built earlier for the same stream.
+    pub fn with_index(r: R, index: FrameIndex) -> FrameReader<R> {
+        FrameReader {
+            r,
+            index,
+            pos: 0,
+            current: None,
+            frame: Vec::new(),
+            compressed: Vec::new(),
+        }
+    }
+
+    pub fn index(&self) -> &FrameIndex {
+        &self.index
+    }
+
+    /// Immutable reader reference.
+    pub fn reader(&self) -> &R {
+        &self.r
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    fn load_frame(&mut self, index: usize) -> Result<()> {
+        if self.current == Some(index) {
+            return Ok(());
+        }
+        self.current = None;
+        let entry = &self.index.frames[index];
+        self.compressed.resize(entry.compressed_size as usize, 0);
+        try!(self.r.seek(SeekFrom::Start(entry.compressed_offset)));
+        try!(self.r.read_exact(&mut self.compressed));
+        self.frame = try!(decompress(&self.compressed));
+        if self.frame.len() as u64 != entry.uncompressed_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Frame content does not match the content size in its header",
+            ));
+        }
+        self.current = Some(index);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for FrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let index = match self.index.position(self.pos) {
+            Some(index) if !buf.is_empty() => index,
+            _ => return Ok(0),
+        };
+        try!(self.load_frame(index));
+        let offset = (self.pos - self.index.frames[index].uncompressed_offset) as usize;
+        let len = cmp::min(buf.len(), self.frame.len() - offset);
+        buf[..len].copy_from_slice(&self.frame[offset..offset + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for FrameReader<R> {
+    /// Moves within the uncompressed data. Seeking past the end is allowed, reads then return no
+    /// data.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.pos = try!(seek_position(pos, self.pos, self.index.content_size));
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::encoder::EncoderBuilder;
+    use super::super::compress;
+    use super::{FrameIndex, FrameReader};
+    use frame::sample;
+    use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+    use xxhash::xxh32;
+
+    fn concatenated(data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        for piece in data.chunks(chunk) {
+            compressed.extend(compress(piece, &EncoderBuilder::new()).unwrap());
+        }
+        compressed
+    }
+
+    #[test]
+    fn test_index_build() {
+        let data = sample(100 * 1000);
+        let mut compressed = concatenated(&data[..50000], 10000);
+        // Skippable frame between data frames
+        compressed.extend_from_slice(&[0x50, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+        compressed.extend(concatenated(&data[50000..], 10000));
+
+        let index = FrameIndex::build(Cursor::new(&compressed)).unwrap();
+        assert_eq!(index.frames().len(), 10);
+        assert_eq!(index.content_size(), data.len() as u64);
+        let frame = index.find(65432).unwrap();
+        assert_eq!(frame.uncompressed_offset, 60000);
+        assert_eq!(frame.uncompressed_size, 10000);
+        let last = &index.frames()[9];
+        assert_eq!(
+            last.compressed_offset + last.compressed_size,
+            compressed.len() as u64
+        );
+        assert!(index.find(data.len() as u64).is_none());
+    }
+
+    #[test]
+    fn test_index_content_size_overflow() {
+        // Empty frame claiming the largest content size
+        let mut frame = vec![0x04, 0x22, 0x4D, 0x18, 0x48, 0x40];
+        frame.extend_from_slice(&u64::MAX.to_le_bytes());
+        let checksum = (xxh32(&frame[4..], 0) >> 8) as u8;
+        frame.push(checksum);
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+
+        let index = FrameIndex::build(Cursor::new(&frame)).unwrap();
+        assert_eq!(index.content_size(), u64::MAX);
+        let mut compressed = frame.clone();
+        compressed.extend_from_slice(&frame);
+        let err = FrameIndex::build(Cursor::new(&compressed)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_index_reader() {
+        let data = sample(1024 * 1024);
+        let compressed = concatenated(&data, 30000);
+        let mut reader = FrameReader::new(Cursor::new(compressed)).unwrap();
+
+        for &(start, len) in [(700000, 50000), (0, 100), (29990, 20), (1048500, 76)].iter() {
+            let mut buffer = vec![0u8; len];
+            reader.seek(SeekFrom::Start(start)).unwrap();
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer[..], &data[start as usize..start as usize + len]);
+        }
+
+        let mut actual = Vec::new();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_index_requires_content_size() {
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(&sample(1000)).unwrap();
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+        let err = FrameIndex::build(Cursor::new(compressed)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // Frames without blocks are empty, whatever their header says
+        let empty = EncoderBuilder::new().build(Vec::new()).unwrap().finish().0;
+        let index = FrameIndex::build(Cursor::new(empty)).unwrap();
+        assert_eq!(index.frames().len(), 1);
+        assert_eq!(index.content_size(), 0);
+    }
+
+    #[test]
+    fn test_index_truncated() {
+        let compressed = concatenated(&sample(50000), 10000);
+        let err = FrameIndex::build(Cursor::new(&compressed[..compressed.len() - 2])).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
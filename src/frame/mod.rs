@@ -0,0 +1,428 @@
+//! This module provides one-shot helpers producing and consuming complete LZ4 frames, the same
+//! format the `lz4` command line tool reads and writes. They are convenient for small payloads
+//! held in memory, where setting up an `Encoder` or `Decoder` over a `Vec` is not worth it.
+//!
+//! It also inspects streams without producing output (`verify()`, `scan()`) and gives random
+//! access to streams of concatenated frames carrying their content size (`FrameReader`). With the
+//! `mmap` feature, `compress_file()` and `decompress_file()` work on memory-mapped files.
+//!
+//! Without the `std` feature, only the one-shot helpers, their variants writing into caller
+//! provided slices and the sans-IO `FrameEncoder` and `FrameDecoder` are available.
+//!
+//! # Examples
+//! ```
+//!
+//! use lz4::EncoderBuilder;
+//! use lz4::frame::{compress, decompress};
+//!
+//! let v = vec![0u8; 1024];
+//!
+//! let compressed = compress(&v, EncoderBuilder::new().level(4)).unwrap();
+//!
+//! assert_eq!(v, decompress(&compressed).unwrap());
+//! ```
+
+use self::codec::buffer_too_small;
+#[cfg(feature = "allocator")]
+use super::allocator::Allocator;
+use super::decoder::DecoderContext;
+use super::encoder::EncoderBuilder;
+use super::error::{Error, ErrorKind, Result};
+use super::liblz4::*;
+use alloc::vec::Vec;
+use core::cmp;
+use core::ptr;
+use libc::size_t;
+
+mod codec;
+#[cfg(feature = "mmap")]
+mod file;
+#[cfg(feature = "std")]
+mod index;
+#[cfg(feature = "std")]
+mod verify;
+
+pub use self::codec::{FrameDecoder, FrameEncoder, HEADER_SIZE_MAX};
+#[cfg(feature = "mmap")]
+pub use self::file::{compress_file, decompress_file};
+#[cfg(feature = "std")]
+pub use self::index::{FrameEntry, FrameIndex, FrameReader};
+#[cfg(feature = "std")]
+pub use self::verify::{scan, verify, FrameReport, FrameSummary};
+#[cfg(feature = "std")]
+pub(crate) use self::verify::{block_checksum_len, le32, read_header, BLOCK_UNCOMPRESSED, MAGIC};
+
+/// Returns the size `compress_to_buffer()` needs to compress src_size bytes with the settings of
+/// the given builder.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the builder compression level is out of
+/// range.
+///
+pub fn compress_bound(src_size: usize, builder: &EncoderBuilder) -> Result<usize> {
+    let preferences = try!(builder.preferences());
+    check_error(unsafe { LZ4F_compressFrameBound(src_size as size_t, &preferences) })
+}
+
+/// Compresses the full src buffer into a single frame using the settings of the given builder.
+/// The source length is stored in the frame header as content size.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the builder compression level is out of
+/// range.
+/// Returns std::io::Error with ErrorKind::Other if the compression failed inside the C library.
+///
+pub fn compress(src: &[u8], builder: &EncoderBuilder) -> Result<Vec<u8>> {
+    let mut preferences = try!(builder.preferences());
+    preferences.frame_info.content_size = src.len() as u64;
+
+    let bound = try!(check_error(unsafe {
+        LZ4F_compressFrameBound(src.len() as size_t, &preferences)
+    }));
+    let mut compressed: Vec<u8> = Vec::with_capacity(bound);
+    unsafe {
+        let len = try!(compress_frame(
+            builder,
+            &preferences,
+            src,
+            compressed.as_mut_ptr(),
+            compressed.capacity(),
+        ));
+        compressed.set_len(len);
+    }
+    Ok(compressed)
+}
+
+/// Compresses the full src buffer into a single frame written to dst, like `compress()`, and
+/// returns the number of bytes written. Does not allocate.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::InvalidInput if the builder compression level is out of
+/// range.
+/// Returns std::io::Error with ErrorKind::WriteZero if dst is smaller than `compress_bound()`.
+/// Returns std::io::Error with ErrorKind::Other if the compression failed inside the C library.
+///
+pub fn compress_to_buffer(src: &[u8], builder: &EncoderBuilder, dst: &mut [u8]) -> Result<usize> {
+    let mut preferences = try!(builder.preferences());
+    preferences.frame_info.content_size = src.len() as u64;
+
+    let bound = try!(check_error(unsafe {
+        LZ4F_compressFrameBound(src.len() as size_t, &preferences)
+    }));
+    if dst.len() < bound {
+        return Err(buffer_too_small());
+    }
+    unsafe { compress_frame(builder, &preferences, src, dst.as_mut_ptr(), dst.len()) }
+}
+
+/// Compresses src into a single frame at dst. The builder allocator, if any, is used for the
+/// compression state, otherwise liblz4 keeps it on the stack.
+#[cfg_attr(not(feature = "allocator"), allow(unused_variables))]
+unsafe fn compress_frame(
+    builder: &EncoderBuilder,
+    preferences: &LZ4FPreferences,
+    src: &[u8],
+    dst: *mut u8,
+    dst_capacity: usize,
+) -> Result<usize> {
+    #[cfg(feature = "allocator")]
+    {
+        if builder.allocator.is_some() {
+            let context = try!(builder.context());
+            return check_error(LZ4F_compressFrame_usingCDict(
+                context.c,
+                dst,
+                dst_capacity as size_t,
+                src.as_ptr(),
+                src.len() as size_t,
+                ptr::null(),
+                preferences,
+            ));
+        }
+    }
+    check_error(LZ4F_compressFrame(
+        dst,
+        dst_capacity as size_t,
+        src.as_ptr(),
+        src.len() as size_t,
+        preferences,
+    ))
+}
+
+/// Decompresses all frames of the src buffer. When a frame header carries the content size, the
+/// output is allocated exactly once for it, otherwise the output grows block by block.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::UnexpectedEof if the src buffer ends in the middle of a
+/// frame.
+/// Returns std::io::Error with ErrorKind::InvalidData if the content size stored in a frame header
+/// cannot be produced from the remaining input.
+/// Returns std::io::Error with ErrorKind::Other if the decompression failed inside the C library.
+/// This is most likely due to malformed input.
+///
+pub fn decompress(src: &[u8]) -> Result<Vec<u8>> {
+    decompress_with(&try!(DecoderContext::new()), src)
+}
+
+/// Decompresses all frames of the src buffer like `decompress()`, allocating the liblz4 context
+/// and its buffers through the given allocator instead of the C `malloc()`, see the `allocator`
+/// module.
+#[cfg(feature = "allocator")]
+pub fn decompress_with_allocator(src: &[u8], allocator: &Allocator) -> Result<Vec<u8>> {
+    decompress_with(&try!(DecoderContext::with_allocator(allocator)), src)
+}
+
+fn decompress_with(context: &DecoderContext, src: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed: Vec<u8> = Vec::new();
+    let mut offset = 0;
+    while offset < src.len() {
+        offset += try!(reserve_frame(context, &src[offset..], &mut decompressed));
+        loop {
+            if decompressed.len() == decompressed.capacity() {
+                decompressed.reserve(BlockSize::Max64KB.get_size());
+            }
+            let len = decompressed.len();
+            let mut src_size = (src.len() - offset) as size_t;
+            let mut dst_size = (decompressed.capacity() - len) as size_t;
+            let next = unsafe {
+                let next = try!(check_error(LZ4F_decompress(
+                    context.c,
+                    decompressed.as_mut_ptr().add(len),
+                    &mut dst_size,
+                    src[offset..].as_ptr(),
+                    &mut src_size,
+                    ptr::null(),
+                )));
+                decompressed.set_len(len + dst_size as usize);
+                next
+            };
+            offset += src_size as usize;
+            if next == 0 {
+                break;
+            }
+            if offset == src.len() && dst_size == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Source buffer ends in the middle of a frame.",
+                ));
+            }
+        }
+    }
+    Ok(decompressed)
+}
+
+/// Decompresses all frames of the src buffer into dst, like `decompress()`, and returns the number
+/// of bytes written. Does not allocate.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::WriteZero if the decompressed data does not fit into
+/// dst.
+/// Returns std::io::Error with ErrorKind::UnexpectedEof if the src buffer ends in the middle of a
+/// frame.
+/// Returns std::io::Error with ErrorKind::Other if the decompression failed inside the C library.
+/// This is most likely due to malformed input.
+///
+pub fn decompress_to_buffer(src: &[u8], dst: &mut [u8]) -> Result<usize> {
+    let context = try!(DecoderContext::new());
+    let mut offset = 0;
+    let mut written = 0;
+    while offset < src.len() {
+        loop {
+            let mut src_size = (src.len() - offset) as size_t;
+            let mut dst_size = (dst.len() - written) as size_t;
+            let next = try!(check_error(unsafe {
+                LZ4F_decompress(
+                    context.c,
+                    dst.as_mut_ptr().add(written),
+                    &mut dst_size,
+                    src[offset..].as_ptr(),
+                    &mut src_size,
+                    ptr::null(),
+                )
+            }));
+            offset += src_size as usize;
+            written += dst_size as usize;
+            if next == 0 {
+                break;
+            }
+            if src_size == 0 && dst_size == 0 {
+                // Stuck with input left means dst is full
+                if offset < src.len() || (written == dst.len() && try!(has_pending(&context))) {
+                    return Err(buffer_too_small());
+                }
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Source buffer ends in the middle of a frame.",
+                ));
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Checks whether the context still holds decompressed data which did not fit into dst.
+fn has_pending(context: &DecoderContext) -> Result<bool> {
+    let mut probe = [0u8; 1];
+    let mut src_size = 0 as size_t;
+    let mut dst_size = probe.len() as size_t;
+    try!(check_error(unsafe {
+        LZ4F_decompress(
+            context.c,
+            probe.as_mut_ptr(),
+            &mut dst_size,
+            ptr::null(),
+            &mut src_size,
+            ptr::null(),
+        )
+    }));
+    Ok(dst_size > 0)
+}
+
+/// Decodes the frame header at the start of src and reserves room for the frame content when
+/// its size is known. Returns the header length.
+fn reserve_frame(context: &DecoderContext, src: &[u8], dst: &mut Vec<u8>) -> Result<usize> {
+    let mut frame_info = LZ4FFrameInfo {
+        block_size_id: BlockSize::Default,
+        block_mode: BlockMode::Linked,
+        content_checksum_flag: ContentChecksum::NoChecksum,
+        frame_type: FrameType::Frame,
+        content_size: 0,
+        dict_id: 0,
+        block_checksum_flag: BlockChecksum::NoBlockChecksum,
+    };
+    let mut src_size = src.len() as size_t;
+    let code = unsafe {
+        LZ4F_getFrameInfo(context.c, &mut frame_info, src.as_ptr(), &mut src_size)
+    };
+    if is_error(code, "ERROR_frameHeader_incomplete") {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Source buffer ends in the middle of a frame header.",
+        ));
+    }
+    try!(check_error(code));
+    if let FrameType::Frame = frame_info.frame_type {
+        try!(check_content_size(frame_info.content_size, src.len() as u64));
+        dst.reserve_exact(frame_info.content_size as usize);
+    }
+    Ok(src_size as usize)
+}
+
+/// Rejects a content size the compressed data can't expand to. A single LZ4 sequence expands to
+/// at most 255 bytes per input byte, so anything larger is a corrupted header rather than a
+/// reason to allocate.
+fn check_content_size(content_size: u64, src_size: u64) -> Result<()> {
+    if content_size > cmp::min(src_size.saturating_mul(255), usize::MAX as u64) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Frame content size is larger than the input can hold.",
+        ));
+    }
+    Ok(())
+}
+
+/// Compressible test data shared by the frame and seekable tests.
+#[cfg(test)]
+pub(crate) fn sample(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8 ^ (i >> 12) as u8).collect()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::super::encoder::EncoderBuilder;
+    use super::super::liblz4::{BlockMode, BlockSize, ContentChecksum};
+    use frame::{
+        compress, compress_bound, compress_to_buffer, decompress, decompress_to_buffer, sample,
+    };
+    use std::io::{ErrorKind, Write};
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let expected = sample(3 * 1024 * 1024 + 17);
+        let builders = [
+            EncoderBuilder::new(),
+            EncoderBuilder::new().level(9).clone(),
+            EncoderBuilder::new()
+                .block_size(BlockSize::Max4MB)
+                .block_mode(BlockMode::Independent)
+                .checksum(ContentChecksum::NoChecksum)
+                .clone(),
+        ];
+        for builder in builders.iter() {
+            let compressed = compress(&expected, builder).unwrap();
+            let actual = decompress(&compressed).unwrap();
+            assert_eq!(expected.len(), actual.capacity());
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn test_frame_empty() {
+        let compressed = compress(&[], &EncoderBuilder::new()).unwrap();
+        assert_eq!(decompress(&compressed).unwrap(), Vec::<u8>::new());
+        assert_eq!(decompress(&[]).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_frame_encoder_stream() {
+        let expected = sample(1024 * 1024);
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(&expected).unwrap();
+        let (mut compressed, result) = encoder.finish();
+        result.unwrap();
+        compressed.extend(compress(&expected, &EncoderBuilder::new()).unwrap());
+
+        let actual = decompress(&compressed).unwrap();
+        assert_eq!(&actual[..expected.len()], &expected[..]);
+        assert_eq!(&actual[expected.len()..], &expected[..]);
+    }
+
+    #[test]
+    fn test_frame_truncated() {
+        let compressed = compress(&sample(100 * 1024), &EncoderBuilder::new()).unwrap();
+        let err = decompress(&compressed[..compressed.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        for len in 1..15 {
+            let err = decompress(&compressed[..len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn test_frame_corrupted() {
+        let mut compressed = compress(&sample(100 * 1024), &EncoderBuilder::new()).unwrap();
+        let len = compressed.len();
+        compressed[len / 2] ^= 0xFF;
+        assert!(decompress(&compressed).is_err());
+    }
+
+    #[test]
+    fn test_frame_to_buffer() {
+        let expected = sample(500 * 1024);
+        let builder = EncoderBuilder::new();
+        let mut compressed = vec![0u8; compress_bound(expected.len(), &builder).unwrap()];
+        let len = compress_to_buffer(&expected, &builder, &mut compressed).unwrap();
+        compressed.truncate(len);
+        assert_eq!(decompress(&compressed).unwrap(), expected);
+
+        let mut actual = vec![0u8; expected.len()];
+        assert_eq!(
+            decompress_to_buffer(&compressed, &mut actual).unwrap(),
+            expected.len()
+        );
+        assert_eq!(actual, expected);
+
+        let err = compress_to_buffer(&expected, &builder, &mut actual[..len]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        let err = decompress_to_buffer(&compressed, &mut actual[1..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        let err = decompress_to_buffer(&compressed[..len - 1], &mut actual).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        // A full buffer is too small even when all the input got consumed
+        for size in &[0, 1, 64 * 1024, expected.len() - 1] {
+            let err = decompress_to_buffer(&compressed, &mut actual[..*size]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::WriteZero);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+use super::super::decoder::DecoderContext;
+use super::super::liblz4::*;
+use libc::size_t;
+use std::io::{self, Error, ErrorKind, Read, Result};
+use std::ptr;
+
+pub(crate) const MAGIC: u32 = 0x184D2204;
+pub(super) const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+pub(super) const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFFFFF0;
+
+const FLG_CONTENT_SIZE: u8 = 0x08;
+const FLG_DICT_ID: u8 = 0x01;
+// Highest bit of a block size marks a block stored uncompressed
+pub(crate) const BLOCK_UNCOMPRESSED: u32 = 0x80000000;
+
+const SCRATCH_SIZE: usize = 64 * 1024;
+
+/// Description of a single frame checked by `verify()`.
+#[derive(Clone, Debug)]
+pub struct FrameSummary {
+    pub block_size: BlockSize,
+    pub block_mode: BlockMode,
+    /// Whether the frame carries a content checksum, which was verified.
+    pub content_checksum: ContentChecksum,
+    /// Whether every block carries a checksum, which were verified.
+    pub block_checksum: BlockChecksum,
+    /// Content size stored in the frame header, if any.
+    pub content_size: Option<u64>,
+    pub blocks: u64,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// Result of a successful `verify()` run.
+#[derive(Clone, Debug, Default)]
+pub struct FrameReport {
+    pub frames: Vec<FrameSummary>,
+    pub skippable_frames: u64,
+    /// Size of the whole input, skippable frames included.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+impl FrameReport {
+    /// Total number of data blocks in all frames.
+    pub fn blocks(&self) -> u64 {
+        self.frames.iter().map(|f| f.blocks).sum()
+    }
+}
+
+/// Decodes every frame of the stream into a scratch buffer, the way `lz4 -t` does, without
+/// producing any output. Header, block and content checksums are validated whenever the frame
+/// carries them, skippable frames are passed over.
+///
+/// # Errors
+/// Returns std::io::Error with ErrorKind::UnexpectedEof if the stream is empty or ends in the
+/// middle of a frame.
+/// Returns std::io::Error with ErrorKind::InvalidData if the stream is not made of LZ4 frames or
+/// a frame does not match its header.
+/// Returns std::io::Error with ErrorKind::Other if the decompression or a checksum failed inside
+/// the C library.
+///
+pub fn verify<R: Read>(r: R) -> Result<FrameReport> {
+    walk(r, true)
+}
+
+/// Reads frame headers and block sizes without decoding any block, which is much cheaper than
+/// `verify()` but only checks the frame headers. Uncompressed sizes are taken from the frame
+/// headers, so they are only known for frames carrying a content size and are zero otherwise.
+///
+/// # Errors
+/// Same as `verify()`, except that block data and checksums are not validated.
+///
+pub fn scan<R: Read>(r: R) -> Result<FrameReport> {
+    walk(r, false)
+}
+
+fn walk<R: Read>(mut r: R, decode: bool) -> Result<FrameReport> {
+    let context = try!(DecoderContext::new());
+    let mut scratch = vec![0u8; SCRATCH_SIZE];
+    let mut block = Vec::new();
+    let mut report = FrameReport::default();
+    loop {
+        let mut magic = [0u8; 4];
+        if !try!(read_magic(&mut r, &mut magic)) {
+            break;
+        }
+        match le32(&magic) {
+            MAGIC => {
+                let frame = if decode {
+                    try!(verify_frame(&context, &mut r, &magic, &mut block, &mut scratch))
+                } else {
+                    let mut frame = try!(scan_frame(&context, &mut r, &magic, skip));
+                    frame.uncompressed_size = frame.content_size.unwrap_or(0);
+                    frame
+                };
+                report.compressed_size += frame.compressed_size;
+                // Header content sizes are not checked by scan(), they may add up to anything
+                report.uncompressed_size =
+                    report.uncompressed_size.saturating_add(frame.uncompressed_size);
+                report.frames.push(frame);
+            }
+            m if m & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC => {
+                let mut size = [0u8; 4];
+                try!(r.read_exact(&mut size));
+                let size = le32(&size) as u64;
+                try!(skip(&mut r, size));
+                report.skippable_frames += 1;
+                report.compressed_size += 8 + size;
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Unrecognized frame magic number",
+                ))
+            }
+        }
+    }
+    if report.frames.is_empty() && report.skippable_frames == 0 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Stream is empty"));
+    }
+    Ok(report)
+}
+
+/// Reads the frame header following the magic number and decodes it with liblz4.
+pub(crate) fn read_header<R: Read>(
+    context: &DecoderContext,
+    r: &mut R,
+    magic: &[u8; 4],
+) -> Result<FrameSummary> {
+    // Magic, FLG, BD, optional content size and dictionary id, header checksum
+    let mut header = [0u8; 19];
+    header[..4].copy_from_slice(magic);
+    try!(r.read_exact(&mut header[4..6]));
+    let mut header_len = 7;
+    if header[4] & FLG_CONTENT_SIZE != 0 {
+        header_len += 8;
+    }
+    if header[4] & FLG_DICT_ID != 0 {
+        header_len += 4;
+    }
+    try!(r.read_exact(&mut header[6..header_len]));
+
+    let mut frame_info = LZ4FFrameInfo {
+        block_size_id: BlockSize::Default,
+        block_mode: BlockMode::Linked,
+        content_checksum_flag: ContentChecksum::NoChecksum,
+        frame_type: FrameType::Frame,
+        content_size: 0,
+        dict_id: 0,
+        block_checksum_flag: BlockChecksum::NoBlockChecksum,
+    };
+    let mut src_size = header_len as size_t;
+    try!(check_error(unsafe {
+        LZ4F_getFrameInfo(context.c, &mut frame_info, header.as_ptr(), &mut src_size)
+    }));
+
+    Ok(FrameSummary {
+        block_size: frame_info.block_size_id.clone(),
+        block_mode: frame_info.block_mode.clone(),
+        content_checksum: frame_info.content_checksum_flag.clone(),
+        block_checksum: frame_info.block_checksum_flag.clone(),
+        content_size: match frame_info.content_size {
+            0 => None,
+            size => Some(size),
+        },
+        blocks: 0,
+        compressed_size: header_len as u64,
+        uncompressed_size: 0,
+    })
+}
+
+pub(crate) fn block_checksum_len(frame: &FrameSummary) -> usize {
+    match frame.block_checksum {
+        BlockChecksum::BlockChecksumEnabled => 4,
+        BlockChecksum::NoBlockChecksum => 0,
+    }
+}
+
+/// Reads the frame header and the block size fields, passing over block data and the content
+/// checksum with `skip`. Leaves the uncompressed size to the caller.
+pub(super) fn scan_frame<R, F>(
+    context: &DecoderContext,
+    r: &mut R,
+    magic: &[u8; 4],
+    mut skip: F,
+) -> Result<FrameSummary>
+where
+    R: Read,
+    F: FnMut(&mut R, u64) -> Result<()>,
+{
+    let mut frame = try!(read_header(context, r, magic));
+    // Blocks are not passed to liblz4, so the context is left in the middle of the frame
+    unsafe { LZ4F_resetDecompressionContext(context.c) };
+
+    let block_limit = frame.block_size.get_size() as u64;
+    loop {
+        let mut size = [0u8; 4];
+        try!(r.read_exact(&mut size));
+        frame.compressed_size += 4;
+        let len = (le32(&size) & !BLOCK_UNCOMPRESSED) as u64;
+        if len == 0 {
+            // End mark, only the content checksum may follow
+            if let ContentChecksum::ChecksumEnabled = frame.content_checksum {
+                try!(skip(r, 4));
+                frame.compressed_size += 4;
+            }
+            break;
+        }
+        if len > block_limit {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Block is larger than the frame block size",
+            ));
+        }
+        let len = len + block_checksum_len(&frame) as u64;
+        try!(skip(r, len));
+        frame.compressed_size += len;
+        frame.blocks += 1;
+    }
+    Ok(frame)
+}
+
+fn verify_frame<R: Read>(
+    context: &DecoderContext,
+    r: &mut R,
+    magic: &[u8; 4],
+    block: &mut Vec<u8>,
+    scratch: &mut [u8],
+) -> Result<FrameSummary> {
+    let mut frame = try!(read_header(context, r, magic));
+    let block_limit = frame.block_size.get_size();
+    let block_checksum_len = block_checksum_len(&frame);
+    loop {
+        let mut size = [0u8; 4];
+        try!(r.read_exact(&mut size));
+        frame.compressed_size += 4;
+        let next = try!(feed(context, &size, scratch, &mut frame.uncompressed_size));
+        let len = (le32(&size) & !BLOCK_UNCOMPRESSED) as usize;
+        if len == 0 {
+            if next != 0 {
+                // Only the content checksum is left
+                block.resize(next, 0);
+                try!(r.read_exact(block));
+                frame.compressed_size += next as u64;
+                if try!(feed(context, block, scratch, &mut frame.uncompressed_size)) != 0 {
+                    return Err(Error::new(ErrorKind::InvalidData, "Frame end is malformed"));
+                }
+            }
+            break;
+        }
+        if len > block_limit {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Block is larger than the frame block size",
+            ));
+        }
+        block.resize(len + block_checksum_len, 0);
+        try!(r.read_exact(block));
+        frame.compressed_size += block.len() as u64;
+        frame.blocks += 1;
+        try!(feed(context, block, scratch, &mut frame.uncompressed_size));
+    }
+
+    if let Some(content_size) = frame.content_size {
+        if content_size != frame.uncompressed_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Frame content does not match the content size in its header",
+            ));
+        }
+    }
+    Ok(frame)
+}
+
+/// Passes the whole src buffer to liblz4, discarding the decoded output. Returns the liblz4 hint
+/// for the next call, which is zero once the frame is complete.
+fn feed(
+    context: &DecoderContext,
+    src: &[u8],
+    scratch: &mut [u8],
+    decoded: &mut u64,
+) -> Result<usize> {
+    let mut offset = 0;
+    loop {
+        let mut src_size = (src.len() - offset) as size_t;
+        let mut dst_size = scratch.len() as size_t;
+        let next = try!(check_error(unsafe {
+            LZ4F_decompress(
+                context.c,
+                scratch.as_mut_ptr(),
+                &mut dst_size,
+                src[offset..].as_ptr(),
+                &mut src_size,
+                ptr::null(),
+            )
+        }));
+        offset += src_size as usize;
+        *decoded += dst_size as u64;
+        // A full scratch buffer may mean liblz4 still holds decoded data
+        if next == 0 || (offset == src.len() && (dst_size as usize) < scratch.len()) {
+            return Ok(next);
+        }
+    }
+}
+
+fn skip<R: Read>(r: &mut R, len: u64) -> Result<()> {
+    if try!(io::copy(&mut r.by_ref().take(len), &mut io::sink())) != len {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Stream ends in the middle of a frame",
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a frame magic number. Returns false on a clean end of stream.
+pub(super) fn read_magic<R: Read>(r: &mut R, magic: &mut [u8; 4]) -> Result<bool> {
+    let mut len = 0;
+    while len < magic.len() {
+        match r.read(&mut magic[len..]) {
+            Ok(0) if len == 0 => return Ok(false),
+            Ok(0) => {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Stream ends in the middle of a frame magic number",
+                ))
+            }
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn le32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::encoder::EncoderBuilder;
+    use super::super::super::liblz4::{BlockChecksum, BlockMode, BlockSize, ContentChecksum};
+    use super::super::compress;
+    use super::{scan, verify};
+    use frame::sample;
+    use std::io::{ErrorKind, Write};
+
+    fn encode(data: &[u8], builder: &EncoderBuilder) -> Vec<u8> {
+        let mut encoder = builder.build(Vec::new()).unwrap();
+        encoder.write_all(data).unwrap();
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+        compressed
+    }
+
+    #[test]
+    fn test_verify_frames() {
+        let data = sample(1024 * 1024 + 1);
+        let mut stream = encode(&data, &EncoderBuilder::new());
+        let first = stream.len();
+        // Skippable frame with 3 bytes of user data
+        stream.extend_from_slice(&[0x5A, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+        stream.extend(
+            compress(
+                &data,
+                EncoderBuilder::new()
+                    .block_size(BlockSize::Max256KB)
+                    .block_mode(BlockMode::Independent)
+                    .checksum(ContentChecksum::NoChecksum),
+            )
+            .unwrap(),
+        );
+
+        let report = verify(&stream[..]).unwrap();
+        assert_eq!(report.frames.len(), 2);
+        assert_eq!(report.skippable_frames, 1);
+        assert_eq!(report.blocks(), 17 + 5);
+        assert_eq!(report.compressed_size, stream.len() as u64);
+        assert_eq!(report.uncompressed_size, 2 * data.len() as u64);
+
+        let frame = &report.frames[0];
+        assert_eq!(frame.block_size, BlockSize::Max64KB);
+        assert_eq!(frame.block_mode, BlockMode::Linked);
+        assert_eq!(frame.content_checksum, ContentChecksum::ChecksumEnabled);
+        assert_eq!(frame.block_checksum, BlockChecksum::NoBlockChecksum);
+        assert_eq!(frame.content_size, None);
+        assert_eq!(frame.compressed_size, first as u64);
+        assert_eq!(frame.uncompressed_size, data.len() as u64);
+
+        let frame = &report.frames[1];
+        assert_eq!(frame.block_size, BlockSize::Max256KB);
+        assert_eq!(frame.block_mode, BlockMode::Independent);
+        assert_eq!(frame.content_checksum, ContentChecksum::NoChecksum);
+        assert_eq!(frame.content_size, Some(data.len() as u64));
+    }
+
+    #[test]
+    fn test_scan_frames() {
+        let data = sample(1024 * 1024 + 1);
+        let mut stream =
+            compress(&data, EncoderBuilder::new().block_size(BlockSize::Max1MB)).unwrap();
+        stream.extend_from_slice(&[0x50, 0x2A, 0x4D, 0x18, 0, 0, 0, 0]);
+        stream.extend(encode(&data, &EncoderBuilder::new()));
+
+        let scanned = scan(&stream[..]).unwrap();
+        let verified = verify(&stream[..]).unwrap();
+        assert_eq!(scanned.frames.len(), 2);
+        assert_eq!(scanned.skippable_frames, 1);
+        assert_eq!(scanned.blocks(), verified.blocks());
+        assert_eq!(scanned.compressed_size, stream.len() as u64);
+        assert_eq!(scanned.frames[0].uncompressed_size, data.len() as u64);
+        assert_eq!(scanned.frames[1].content_size, None);
+        assert_eq!(scanned.frames[1].uncompressed_size, 0);
+
+        // Block content is not checked
+        let len = stream.len();
+        stream[len - 1] ^= 0x01;
+        assert!(scan(&stream[..]).is_ok());
+        assert!(scan(&stream[..len - 1]).is_err());
+    }
+
+    #[test]
+    fn test_verify_content_checksum() {
+        let mut stream = encode(&sample(100 * 1024), &EncoderBuilder::new());
+        let len = stream.len();
+        stream[len - 1] ^= 0x01;
+        assert!(verify(&stream[..]).is_err());
+    }
+
+    #[test]
+    fn test_verify_truncated() {
+        let stream = encode(&sample(100 * 1024), &EncoderBuilder::new());
+        for len in [0, 2, 6, 100, stream.len() - 1].iter() {
+            let err = verify(&stream[..*len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn test_verify_garbage() {
+        let err = verify(&b"not an lz4 stream"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,17 +1,55 @@
+// Without the std feature, only the parts that don't need std::io are built: block, the one-shot
+// and sans-IO frame functions, and xxhash. They still allocate through alloc.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[macro_use]
+extern crate alloc;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 extern crate libc;
 extern crate lz4_sys;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+
+// wasm32-unknown-unknown has no C library, these match the types lz4-sys declares there
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[allow(non_camel_case_types)]
+mod libc {
+    pub use core::ffi::{c_char, c_int, c_void};
+    pub type size_t = usize;
+}
 
+#[cfg(feature = "allocator")]
+pub mod allocator;
+pub mod error;
 pub mod liblz4;
 
 mod decoder;
 mod encoder;
 
 pub mod block;
+pub mod frame;
+#[cfg(feature = "std")]
+pub mod seekable;
+#[cfg(feature = "std")]
+pub mod util;
+pub mod xxhash;
 
+pub use decoder::ChecksumMode;
+#[cfg(feature = "std")]
 pub use decoder::Decoder;
+#[cfg(feature = "std")]
+pub use decoder::DecoderBuilder;
+pub use encoder::CompressionLevel;
+#[cfg(feature = "std")]
 pub use encoder::Encoder;
 pub use encoder::EncoderBuilder;
 pub use liblz4::version;
 pub use liblz4::BlockMode;
 pub use liblz4::BlockSize;
 pub use liblz4::ContentChecksum;
+#[cfg(feature = "std")]
+pub use seekable::SeekableDecoder;
+#[cfg(feature = "std")]
+pub use seekable::SeekableEncoder;
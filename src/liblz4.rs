@@ -1,9 +1,9 @@
-use std::ffi::CStr;
-use std::fmt::Display;
-use std::fmt::Formatter;
-use std::io::Error;
-use std::io::ErrorKind;
-use std::str;
+use super::error::{Error, ErrorKind};
+use alloc::string::{String, ToString};
+use core::ffi::CStr;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use core::str;
 
 pub use lz4_sys::*;
 
@@ -11,11 +11,12 @@ pub use lz4_sys::*;
 pub struct LZ4Error(String);
 
 impl Display for LZ4Error {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), ::std::fmt::Error> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), ::core::fmt::Error> {
         write!(f, "LZ4 error: {}", &self.0)
     }
 }
 
+#[cfg(feature = "std")]
 impl ::std::error::Error for LZ4Error {
     fn description(&self) -> &str {
         &self.0
@@ -30,24 +31,34 @@ pub fn check_error(code: LZ4FErrorCode) -> Result<usize, Error> {
     unsafe {
         if LZ4F_isError(code) != 0 {
             let error_name = LZ4F_getErrorName(code);
-            return Err(Error::new(
-                ErrorKind::Other,
-                LZ4Error(
-                    str::from_utf8(CStr::from_ptr(error_name).to_bytes())
-                        .unwrap()
-                        .to_string(),
-                ),
-            ));
+            let error = LZ4Error(
+                str::from_utf8(CStr::from_ptr(error_name).to_bytes())
+                    .unwrap()
+                    .to_string(),
+            );
+            #[cfg(not(feature = "std"))]
+            let error = error.to_string();
+            return Err(Error::new(ErrorKind::Other, error));
         }
     }
     Ok(code as usize)
 }
 
+/// Checks whether code is the liblz4 error with the given name, such as
+/// `ERROR_frameHeader_incomplete`. The error numbers are not part of the liblz4 API, the names are.
+pub(crate) fn is_error(code: LZ4FErrorCode, name: &str) -> bool {
+    unsafe {
+        LZ4F_isError(code) != 0
+            && CStr::from_ptr(LZ4F_getErrorName(code)).to_bytes() == name.as_bytes()
+    }
+}
+
 pub fn version() -> i32 {
     unsafe { LZ4_versionNumber() }
 }
 
 #[test]
 fn test_version_number() {
-    version();
+    // Bindings describe liblz4 v1.9.0, older system libraries are not supported
+    assert!(version() >= 10900);
 }
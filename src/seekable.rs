@@ -0,0 +1,525 @@
+//! This module provides a seekable variant of the LZ4 frame format, allowing arbitrary byte ranges
+//! to be read without decoding the stream from its start.
+//!
+//! A seekable stream is a regular LZ4 frame made of independent blocks, followed by a skippable
+//! frame holding the compressed and uncompressed size of every block. Any LZ4 decoder can still
+//! read it sequentially and ignores the index, while `SeekableDecoder` uses the index to jump to
+//! the block covering the requested position and decodes only that block.
+//!
+//! The index is stored as a skippable frame (all integers little-endian):
+//!
+//! | Field | Size |
+//! |-------|------|
+//! | Skippable frame magic `0x184D2A5E` | 4 bytes |
+//! | Frame size, `8 * blocks + 9` | 4 bytes |
+//! | For each block: compressed size (block header included), uncompressed size | 8 bytes |
+//! | Number of blocks | 4 bytes |
+//! | Descriptor, reserved and set to 0 | 1 byte |
+//! | Seekable magic `0x53345A4C` (`"LZ4S"`) | 4 bytes |
+//!
+//! # Examples
+//! ```
+//!
+//! use lz4::EncoderBuilder;
+//! use lz4::SeekableDecoder;
+//! use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+//!
+//! let data: Vec<u8> = (0..1000000).map(|i| (i % 251) as u8).collect();
+//!
+//! let mut encoder = EncoderBuilder::new().build_seekable(Vec::new()).unwrap();
+//! encoder.write_all(&data).unwrap();
+//! let (compressed, result) = encoder.finish();
+//! result.unwrap();
+//!
+//! let mut decoder = SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+//! decoder.seek(SeekFrom::Start(500000)).unwrap();
+//! let mut buffer = [0u8; 100];
+//! decoder.read_exact(&mut buffer).unwrap();
+//! assert_eq!(&buffer[..], &data[500000..500100]);
+//! ```
+
+use super::block;
+use super::decoder::DecoderContext;
+use super::encoder::{Encoder, EncoderBuilder};
+use super::frame::{block_checksum_len, le32, read_header, BLOCK_UNCOMPRESSED, MAGIC};
+use super::liblz4::BlockMode;
+use std::cmp;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::mem;
+
+const INDEX_MAGIC: u32 = 0x184D2A5E;
+const SEEKABLE_MAGIC: u32 = 0x53345A4C;
+
+const ENTRY_SIZE: u64 = 8;
+// Number of blocks, descriptor and seekable magic
+const FOOTER_SIZE: u64 = 9;
+
+/// Encoder writing a seekable stream. Data is compressed as a single frame of independent blocks,
+/// the index is appended by `finish()`.
+pub struct SeekableEncoder<W: Write> {
+    encoder: Encoder<CountingWriter<W>>,
+    limit: usize,
+    // Start of the next block, until it is complete
+    block: Vec<u8>,
+    // Compressed and uncompressed size of every block written so far
+    entries: Vec<(u32, u32)>,
+}
+
+struct CountingWriter<W> {
+    w: W,
+    count: u64,
+}
+
+impl EncoderBuilder {
+    /// Creates a `SeekableEncoder` using the settings of this builder. The block mode is always
+    /// independent, as blocks must be decodable on their own.
+    pub fn build_seekable<W: Write>(&self, w: W) -> Result<SeekableEncoder<W>> {
+        let mut builder = self.clone();
+        builder.block_mode(BlockMode::Independent);
+        let limit = try!(builder.preferences()).frame_info.block_size_id.get_size();
+        Ok(SeekableEncoder {
+            encoder: try!(builder.build(CountingWriter { w, count: 0 })),
+            limit,
+            block: Vec::with_capacity(limit),
+            entries: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> SeekableEncoder<W> {
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        &self.encoder.writer().w
+    }
+
+    fn write_block(&mut self, data: &[u8]) -> Result<()> {
+        let start = self.encoder.writer().count;
+        try!(self.encoder.write_all(data));
+        if data.len() < self.limit {
+            // Partial blocks stay in the context until flushed
+            try!(self.encoder.flush());
+        }
+        let compressed = self.encoder.writer().count - start;
+        self.entries.push((compressed as u32, data.len() as u32));
+        Ok(())
+    }
+
+    fn write_buffered(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let block = mem::take(&mut self.block);
+        let result = self.write_block(&block);
+        self.block = block;
+        self.block.clear();
+        result
+    }
+
+    /// Finishes the frame, writes the index and returns the wrapped writer.
+    pub fn finish(mut self) -> (W, Result<()>) {
+        let result = self.write_buffered();
+        let entries = self.entries;
+        let (mut counter, finished) = self.encoder.finish();
+        let result = result
+            .and(finished)
+            .and_then(|()| write_index(&mut counter.w, &entries))
+            .and_then(|()| counter.w.flush());
+        (counter.w, result)
+    }
+}
+
+impl<W: Write> Write for SeekableEncoder<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let size = cmp::min(self.limit - self.block.len(), buffer.len() - offset);
+            if self.block.is_empty() && size == self.limit {
+                // Whole block available, no need to copy it
+                try!(self.write_block(&buffer[offset..offset + size]));
+            } else {
+                self.block.extend_from_slice(&buffer[offset..offset + size]);
+                if self.block.len() == self.limit {
+                    try!(self.write_buffered());
+                }
+            }
+            offset += size;
+        }
+        Ok(buffer.len())
+    }
+
+    /// Writes buffered data as a shorter block, which gets its own index entry.
+    fn flush(&mut self) -> Result<()> {
+        try!(self.write_buffered());
+        self.encoder.flush()
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let len = try!(self.w.write(buffer));
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.w.flush()
+    }
+}
+
+fn write_index<W: Write>(w: &mut W, entries: &[(u32, u32)]) -> Result<()> {
+    let size = ENTRY_SIZE * entries.len() as u64 + FOOTER_SIZE;
+    if size > u32::MAX as u64 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Too many blocks for the seekable index.",
+        ));
+    }
+    let mut index = Vec::with_capacity(8 + size as usize);
+    index.extend_from_slice(&INDEX_MAGIC.to_le_bytes());
+    index.extend_from_slice(&(size as u32).to_le_bytes());
+    for &(compressed, uncompressed) in entries {
+        index.extend_from_slice(&compressed.to_le_bytes());
+        index.extend_from_slice(&uncompressed.to_le_bytes());
+    }
+    index.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    index.push(0);
+    index.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+    w.write_all(&index)
+}
+
+struct Entry {
+    // Position of the block header in the stream
+    offset: u64,
+    compressed_size: u32,
+    // Position of the block content in the uncompressed data
+    start: u64,
+    size: u32,
+}
+
+/// Decoder for seekable streams, providing random access to the uncompressed data through
+/// `Read` and `Seek`. Only the block covering the current position is decoded and kept in memory.
+///
+/// The stream must start with the frame and end with the index, as written by `SeekableEncoder`.
+pub struct SeekableDecoder<R> {
+    r: R,
+    entries: Vec<Entry>,
+    size: u64,
+    pos: u64,
+    // Index of the block held in `block`
+    current: Option<usize>,
+    block: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl<R: Read + Seek> SeekableDecoder<R> {
+    /// Reads the index and the frame header of the stream. The seekable frame starts at the
+    /// current position of the reader and runs to the end of the stream, so it can follow other
+    /// data.
+    ///
+    /// # Errors
+    /// Returns std::io::Error with ErrorKind::InvalidData if the stream does not end with a
+    /// seekable index or the index does not match the frame.
+    ///
+    pub fn new(mut r: R) -> Result<SeekableDecoder<R>> {
+        let base = try!(r.stream_position());
+        let end = try!(r.seek(SeekFrom::End(0)));
+        if end < base + FOOTER_SIZE {
+            return Err(invalid("Stream is too short to hold a seekable index."));
+        }
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        try!(r.seek(SeekFrom::Start(end - FOOTER_SIZE)));
+        try!(r.read_exact(&mut footer));
+        if le32(&footer[5..]) != SEEKABLE_MAGIC {
+            return Err(invalid("Stream does not end with a seekable index."));
+        }
+        let blocks = le32(&footer[..4]) as u64;
+        let index_size = 8 + ENTRY_SIZE * blocks + FOOTER_SIZE;
+        if index_size > end - base {
+            return Err(invalid("Seekable index is larger than the stream."));
+        }
+        let index_start = end - index_size;
+        let mut index = vec![0u8; (index_size - FOOTER_SIZE) as usize];
+        try!(r.seek(SeekFrom::Start(index_start)));
+        try!(r.read_exact(&mut index));
+        if le32(&index) != INDEX_MAGIC || le32(&index[4..]) as u64 != index_size - 8 {
+            return Err(invalid("Seekable index frame is malformed."));
+        }
+
+        let mut magic = [0u8; 4];
+        try!(r.seek(SeekFrom::Start(base)));
+        try!(r.read_exact(&mut magic));
+        if le32(&magic) != MAGIC {
+            return Err(invalid("Stream does not start with an LZ4 frame."));
+        }
+        let frame = try!(read_header(&try!(DecoderContext::new()), &mut r, &magic));
+        let mut offset = base + frame.compressed_size;
+        // The index sizes the block buffers, it must not go beyond what the frame allows
+        let size_limit = frame.block_size.get_size() as u32;
+        let compressed_limit = 4 + size_limit + block_checksum_len(&frame) as u32;
+
+        let mut entries = Vec::with_capacity(blocks as usize);
+        let mut start = 0;
+        for entry in index[8..].chunks(ENTRY_SIZE as usize) {
+            let compressed_size = le32(entry);
+            let size = le32(&entry[4..]);
+            if compressed_size < 4 || size == 0 {
+                return Err(invalid("Seekable index holds an empty block."));
+            }
+            if compressed_size > compressed_limit || size > size_limit {
+                return Err(invalid("Seekable index holds a block larger than the frame allows."));
+            }
+            entries.push(Entry {
+                offset,
+                compressed_size,
+                start,
+                size,
+            });
+            offset += compressed_size as u64;
+            start += size as u64;
+        }
+        if offset > index_start {
+            return Err(invalid("Seekable index does not match the frame."));
+        }
+        Ok(SeekableDecoder {
+            r,
+            entries,
+            size: start,
+            pos: 0,
+            current: None,
+            block: Vec::new(),
+            compressed: Vec::new(),
+        })
+    }
+
+    /// Immutable reader reference.
+    pub fn reader(&self) -> &R {
+        &self.r
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    /// Size of the uncompressed data.
+    pub fn content_size(&self) -> u64 {
+        self.size
+    }
+
+    /// Number of blocks listed in the index.
+    pub fn blocks(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn load_block(&mut self, index: usize) -> Result<()> {
+        if self.current == Some(index) {
+            return Ok(());
+        }
+        self.current = None;
+        let entry = &self.entries[index];
+        self.compressed.resize(entry.compressed_size as usize, 0);
+        try!(self.r.seek(SeekFrom::Start(entry.offset)));
+        try!(self.r.read_exact(&mut self.compressed));
+
+        let header = le32(&self.compressed);
+        let len = (header & !BLOCK_UNCOMPRESSED) as usize;
+        if len + 4 > self.compressed.len() {
+            return Err(invalid("Block is larger than its index entry."));
+        }
+        // A block checksum may follow the block data, it is not checked
+        let data = &self.compressed[4..4 + len];
+        self.block = if header & BLOCK_UNCOMPRESSED != 0 {
+            data.to_vec()
+        } else {
+            try!(block::decompress(data, Some(entry.size as i32)))
+        };
+        if self.block.len() != entry.size as usize {
+            return Err(invalid("Block size does not match its index entry."));
+        }
+        self.current = Some(index);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let pos = self.pos;
+        let index = self
+            .entries
+            .partition_point(|e| e.start + e.size as u64 <= pos);
+        try!(self.load_block(index));
+        let offset = (pos - self.entries[index].start) as usize;
+        let len = cmp::min(buf.len(), self.block.len() - offset);
+        buf[..len].copy_from_slice(&self.block[offset..offset + len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableDecoder<R> {
+    /// Moves within the uncompressed data. Seeking past the end is allowed, reads then return no
+    /// data.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.pos = try!(seek_position(pos, self.pos, self.size));
+        Ok(self.pos)
+    }
+}
+
+/// Resolves a seek within uncompressed data of the given size.
+pub(crate) fn seek_position(pos: SeekFrom, current: u64, size: u64) -> Result<u64> {
+    let (base, offset) = match pos {
+        SeekFrom::Start(pos) => (pos, 0),
+        SeekFrom::End(offset) => (size, offset),
+        SeekFrom::Current(offset) => (current, offset),
+    };
+    let pos = if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    };
+    pos.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid seek to a negative or overflowing position.",
+        )
+    })
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::encoder::EncoderBuilder;
+    use super::super::liblz4::BlockSize;
+    use super::SeekableDecoder;
+    use frame;
+    use frame::sample;
+    use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+    fn encode(data: &[u8], chunk: usize) -> Vec<u8> {
+        let mut encoder = EncoderBuilder::new()
+            .block_size(BlockSize::Max64KB)
+            .build_seekable(Vec::new())
+            .unwrap();
+        for piece in data.chunks(chunk) {
+            encoder.write_all(piece).unwrap();
+        }
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+        compressed
+    }
+
+    #[test]
+    fn test_seekable_random_access() {
+        let data = sample(1024 * 1024 + 123);
+        let compressed = encode(&data, 10000);
+        let mut decoder = SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(decoder.blocks(), 17);
+        assert_eq!(decoder.content_size(), data.len() as u64);
+
+        for &(start, len) in [(700000, 5000), (0, 100), (65530, 20), (1048570, 129)].iter() {
+            let mut buffer = vec![0u8; len];
+            assert_eq!(decoder.seek(SeekFrom::Start(start)).unwrap(), start);
+            decoder.read_exact(&mut buffer).unwrap();
+            assert_eq!(&buffer[..], &data[start as usize..start as usize + len]);
+        }
+
+        let mut tail = Vec::new();
+        decoder.seek(SeekFrom::End(-10)).unwrap();
+        decoder.read_to_end(&mut tail).unwrap();
+        assert_eq!(&tail[..], &data[data.len() - 10..]);
+        assert_eq!(
+            decoder.seek(SeekFrom::Current(-20)).unwrap(),
+            data.len() as u64 - 20
+        );
+        let err = decoder
+            .seek(SeekFrom::Current(-(data.len() as i64)))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_seekable_after_other_data() {
+        let data = sample(300 * 1024);
+        let mut stream = b"some leading data".to_vec();
+        let base = stream.len() as u64;
+        stream.extend_from_slice(&encode(&data, 10000));
+        let mut r = Cursor::new(stream);
+        r.seek(SeekFrom::Start(base)).unwrap();
+        let mut decoder = SeekableDecoder::new(r).unwrap();
+        assert_eq!(decoder.content_size(), data.len() as u64);
+        let mut buffer = vec![0u8; 1000];
+        decoder.seek(SeekFrom::Start(200000)).unwrap();
+        decoder.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[200000..201000]);
+    }
+
+    #[test]
+    fn test_seekable_sequential() {
+        let data = sample(300 * 1024);
+        let compressed = encode(&data, 65536);
+        // Regular decoders skip the index
+        assert_eq!(frame::decompress(&compressed).unwrap(), data);
+
+        let mut decoder = SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, data);
+    }
+
+    #[test]
+    fn test_seekable_flush() {
+        let data = sample(100 * 1024);
+        let mut encoder = EncoderBuilder::new()
+            .block_size(BlockSize::Max64KB)
+            .build_seekable(Vec::new())
+            .unwrap();
+        encoder.write_all(&data[..1000]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&data[1000..]).unwrap();
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+
+        let mut decoder = SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(decoder.blocks(), 3);
+        let mut buffer = [0u8; 100];
+        decoder.seek(SeekFrom::Start(950)).unwrap();
+        decoder.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer[..], &data[950..1050]);
+    }
+
+    #[test]
+    fn test_seekable_empty() {
+        let compressed = encode(&[], 1);
+        let mut decoder = SeekableDecoder::new(Cursor::new(compressed)).unwrap();
+        assert_eq!(decoder.blocks(), 0);
+        let mut actual = Vec::new();
+        decoder.read_to_end(&mut actual).unwrap();
+        assert!(actual.is_empty());
+    }
+
+    #[test]
+    fn test_seekable_invalid() {
+        let compressed = frame::compress(&sample(1000), &EncoderBuilder::new()).unwrap();
+        let err = SeekableDecoder::new(Cursor::new(compressed)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut compressed = encode(&sample(1000), 1000);
+        let len = compressed.len();
+        // Number of blocks in the footer
+        compressed[len - 9] = 200;
+        let err = SeekableDecoder::new(Cursor::new(compressed)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // Uncompressed size of the last block, beyond the frame block size
+        let mut compressed = encode(&sample(1000), 1000);
+        let len = compressed.len();
+        compressed[len - 10] = 0x7F;
+        let err = SeekableDecoder::new(Cursor::new(compressed)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,244 @@
+//! Helpers for writing decompressed data.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Cursor, Result, Seek, SeekFrom, Write};
+use std::mem;
+
+// Runs of zeros are looked for in pieces of this size, aligned to the output position. It
+// matches the usual file system block size, the granularity holes are allocated with.
+const SEGMENT_SIZE: u64 = 4 * 1024;
+
+/// Writer creating sparse files: aligned runs of zero bytes are skipped with a seek instead of
+/// being written, so the file system does not allocate blocks for them.
+///
+/// The wrapped writer must be positioned at its end (e.g. a newly created or truncated file),
+/// otherwise the skipped ranges keep their previous content. `finish()` has to be called once
+/// all data is written, to extend the output over trailing zeros with `SetLen::set_len()`.
+pub struct SparseWriter<W> {
+    w: W,
+    // Amount of data passed to the wrapped writer, either written or skipped
+    pos: u64,
+    // Zero bytes skipped but not yet seeked over
+    skip: u64,
+    // Start of the next segment, until it is complete
+    segment: Vec<u8>,
+}
+
+/// Output whose length can be set, like `File::set_len()`. Extending it must not allocate the
+/// added range, so a trailing run of zeros stays a hole.
+pub trait SetLen {
+    fn set_len(&mut self, size: u64) -> Result<()>;
+}
+
+impl SetLen for File {
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        File::set_len(self, size)
+    }
+}
+
+impl SetLen for &File {
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        File::set_len(self, size)
+    }
+}
+
+impl SetLen for Cursor<Vec<u8>> {
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        self.get_mut().resize(size as usize, 0);
+        Ok(())
+    }
+}
+
+impl<W: SetLen + ?Sized> SetLen for &mut W {
+    fn set_len(&mut self, size: u64) -> Result<()> {
+        (**self).set_len(size)
+    }
+}
+
+impl<W: Write + Seek + SetLen> SparseWriter<W> {
+    pub fn new(w: W) -> SparseWriter<W> {
+        SparseWriter {
+            w,
+            pos: 0,
+            skip: 0,
+            segment: Vec::with_capacity(SEGMENT_SIZE as usize),
+        }
+    }
+
+    /// Immutable writer reference.
+    pub fn writer(&self) -> &W {
+        &self.w
+    }
+
+    fn segment_left(&self) -> usize {
+        (SEGMENT_SIZE - self.pos % SEGMENT_SIZE) as usize - self.segment.len()
+    }
+
+    fn write_segment(&mut self, segment: &[u8]) -> Result<()> {
+        if segment.iter().all(|&b| b == 0) {
+            self.skip += segment.len() as u64;
+        } else {
+            if self.skip > 0 {
+                try!(self.w.seek(SeekFrom::Current(self.skip as i64)));
+                self.skip = 0;
+            }
+            try!(self.w.write_all(segment));
+        }
+        self.pos += segment.len() as u64;
+        Ok(())
+    }
+
+    fn write_buffered(&mut self) -> Result<()> {
+        let segment = mem::take(&mut self.segment);
+        let result = self.write_segment(&segment);
+        self.segment = segment;
+        self.segment.clear();
+        result
+    }
+
+    /// Writes buffered data, extends the output over a trailing run of
+    /// zeros, giving it its full length, and returns the wrapped writer.
+    pub fn finish(mut self) -> (W, Result<()>) {
+        let result = self.write_buffered().and_then(|()| {
+            try!(self.w.flush());
+            if self.skip > 0 {
+                // Seeking past the end does not extend the output
+                let end = try!(self.w.seek(SeekFrom::Current(self.skip as i64)));
+                try!(self.w.set_len(end));
+                self.skip = 0;
+            }
+            Ok(())
+        });
+        (self.w, result)
+    }
+}
+
+impl<W: Write + Seek + SetLen> Write for SparseWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let size = cmp::min(self.segment_left(), buffer.len() - offset);
+            if self.segment.is_empty() && size == self.segment_left() {
+                // Whole segment available, no need to copy it
+                try!(self.write_segment(&buffer[offset..offset + size]));
+            } else {
+                self.segment
+                    .extend_from_slice(&buffer[offset..offset + size]);
+                if self.segment_left() == 0 {
+                    try!(self.write_buffered());
+                }
+            }
+            offset += size;
+        }
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        try!(self.write_buffered());
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SetLen, SparseWriter};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Cursor, Result, Seek, SeekFrom, Write};
+    use std::process;
+
+    struct CountingWrapper {
+        c: Cursor<Vec<u8>>,
+        written: usize,
+    }
+
+    impl Write for CountingWrapper {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = try!(self.c.write(buf));
+            self.written += len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for CountingWrapper {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+            self.c.seek(pos)
+        }
+    }
+
+    impl SetLen for CountingWrapper {
+        fn set_len(&mut self, size: u64) -> Result<()> {
+            self.c.set_len(size)
+        }
+    }
+
+    fn sparse_data() -> Vec<u8> {
+        let mut data = vec![0u8; 64 * 1024];
+        data[100] = 1;
+        for (i, b) in data.iter_mut().enumerate().take(30000).skip(20000) {
+            *b = i as u8;
+        }
+        data[40000] = 2;
+        data
+    }
+
+    fn write_sparse(data: &[u8], chunk: usize) -> CountingWrapper {
+        let mut writer = SparseWriter::new(CountingWrapper {
+            c: Cursor::new(Vec::new()),
+            written: 0,
+        });
+        for piece in data.chunks(chunk) {
+            writer.write_all(piece).unwrap();
+        }
+        let (inner, result) = writer.finish();
+        result.unwrap();
+        inner
+    }
+
+    #[test]
+    fn test_sparse_content() {
+        let data = sparse_data();
+        for chunk in [1, 777, 4096, 65536].iter() {
+            let inner = write_sparse(&data, *chunk);
+            assert_eq!(inner.c.get_ref(), &data);
+        }
+    }
+
+    #[test]
+    fn test_sparse_skips_zeros() {
+        let data = sparse_data();
+        let inner = write_sparse(&data, 1000);
+        assert_eq!(inner.c.get_ref(), &data);
+        // Segments holding data[100], data[20000..30000] and data[40000]
+        assert_eq!(inner.written, 4096 + 4 * 4096 + 4096);
+    }
+
+    #[test]
+    fn test_sparse_only_zeros() {
+        let inner = write_sparse(&[0u8; 10000], 3000);
+        assert_eq!(inner.c.get_ref(), &vec![0u8; 10000]);
+        assert_eq!(inner.written, 0);
+
+        let inner = write_sparse(&[], 1);
+        assert!(inner.c.get_ref().is_empty());
+    }
+
+    #[test]
+    fn test_sparse_file() {
+        let path = env::temp_dir().join(format!("lz4-rs-{}-sparse", process::id()));
+        let data = sparse_data();
+        let mut file = File::create(&path).unwrap();
+        let mut writer = SparseWriter::new(&mut file);
+        writer.write_all(&data).unwrap();
+        let (_, result) = writer.finish();
+        result.unwrap();
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), data);
+        fs::remove_file(&path).unwrap();
+    }
+}
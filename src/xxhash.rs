@@ -0,0 +1,136 @@
+//! This module exposes the xxHash32 implementation bundled with liblz4, which is the hash used
+//! for LZ4 frame header, block and content checksums.
+//!
+//! # Examples
+//! ```
+//!
+//! use lz4::xxhash::{xxh32, XxHash32};
+//! use std::hash::Hasher;
+//!
+//! let mut hasher = XxHash32::new();
+//! hasher.write(b"Some ");
+//! hasher.write(b"data");
+//!
+//! assert_eq!(hasher.digest(), xxh32(b"Some data", 0));
+//! ```
+
+use super::liblz4::*;
+use libc::size_t;
+use core::hash::Hasher;
+
+/// Calculates the xxHash32 of the whole data buffer in one call.
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    unsafe { XXH32(data.as_ptr(), data.len() as size_t, seed) }
+}
+
+/// Streaming xxHash32 calculation. `Hasher::finish()` returns the 32-bit digest widened to
+/// `u64`, `digest()` returns it as is.
+pub struct XxHash32 {
+    state: *mut XXH32State,
+}
+
+unsafe impl Send for XxHash32 {}
+
+impl XxHash32 {
+    /// Creates a hasher with a zero seed, as used by LZ4 frame checksums.
+    pub fn new() -> Self {
+        XxHash32::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u32) -> Self {
+        let state = unsafe { XXH32_createState() };
+        assert!(!state.is_null(), "Unable to allocate xxHash32 state");
+        unsafe { XXH32_reset(state, seed) };
+        XxHash32 { state }
+    }
+
+    /// Restarts the calculation from scratch with the given seed.
+    pub fn reset(&mut self, seed: u32) {
+        unsafe { XXH32_reset(self.state, seed) };
+    }
+
+    /// Digest of the data written so far. The hasher can still be fed afterwards.
+    pub fn digest(&self) -> u32 {
+        unsafe { XXH32_digest(self.state) }
+    }
+}
+
+impl Default for XxHash32 {
+    fn default() -> Self {
+        XxHash32::new()
+    }
+}
+
+impl Hasher for XxHash32 {
+    fn finish(&self) -> u64 {
+        self.digest() as u64
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        unsafe { XXH32_update(self.state, bytes.as_ptr(), bytes.len() as size_t) };
+    }
+}
+
+impl Drop for XxHash32 {
+    fn drop(&mut self) {
+        unsafe { XXH32_freeState(self.state) };
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::super::encoder::EncoderBuilder;
+    use super::{xxh32, XxHash32};
+    use std::hash::Hasher;
+    use std::io::Write;
+
+    #[test]
+    fn test_xxh32_known_values() {
+        assert_eq!(xxh32(b"", 0), 0x02CC5D05);
+        assert_eq!(xxh32(b"", 1), 0x0B2CB792);
+        assert_eq!(xxh32(b"abc", 0), 0x32D153FF);
+    }
+
+    #[test]
+    fn test_xxhash32_streaming() {
+        let mut data = Vec::new();
+        for i in 0..100_000 {
+            data.push((i % 253) as u8);
+        }
+        for seed in [0, 1, 0xDEADBEEF].iter() {
+            let mut hasher = XxHash32::with_seed(*seed);
+            for chunk in data.chunks(777) {
+                hasher.write(chunk);
+            }
+            assert_eq!(hasher.digest(), xxh32(&data, *seed));
+            assert_eq!(hasher.finish(), xxh32(&data, *seed) as u64);
+
+            hasher.reset(*seed);
+            hasher.write(b"abc");
+            assert_eq!(hasher.digest(), xxh32(b"abc", *seed));
+        }
+    }
+
+    #[test]
+    fn test_xxhash32_content_checksum() {
+        let data = b"Some data protected by the frame content checksum";
+        let mut encoder = EncoderBuilder::new().build(Vec::new()).unwrap();
+        encoder.write_all(data).unwrap();
+        let (compressed, result) = encoder.finish();
+        result.unwrap();
+
+        // The content checksum is stored little-endian after the end mark
+        let stored = &compressed[compressed.len() - 4..];
+        let stored = (stored[0] as u32)
+            | (stored[1] as u32) << 8
+            | (stored[2] as u32) << 16
+            | (stored[3] as u32) << 24;
+        assert_eq!(stored, xxh32(data, 0));
+    }
+
+    #[test]
+    fn test_xxhash32_send() {
+        fn check_send<S: Send>(_: &S) {}
+        check_send(&XxHash32::new());
+    }
+}
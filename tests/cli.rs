@@ -0,0 +1,417 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+use std::thread;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("lz4-cli-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src").join("sub")).unwrap();
+    fs::write(
+        dir.join("src").join("sub").join("data.txt"),
+        b"some data to compress",
+    )
+    .unwrap();
+    dir
+}
+
+fn lz4(dir: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_lz4"))
+        .current_dir(dir)
+        .arg("-q")
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Runs lz4 with the given standard input and collects what it prints.
+fn run(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lz4"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Fed from another thread, lz4 may fill its stdout pipe before reading all of it, or
+    // exit without reading it at all
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap();
+    output
+}
+
+/// Compressible text, which higher levels compress better.
+fn text() -> Vec<u8> {
+    let lines: Vec<String> = (0..20000u32)
+        .map(|i| {
+            format!(
+                "line {}: the quick brown fox {} jumps over {} lazy dogs\n",
+                i,
+                i * 7919 % 10007,
+                i * 31 % 1000
+            )
+        })
+        .collect();
+    lines.concat().into_bytes()
+}
+
+#[test]
+fn test_output_dir_single_file() {
+    let dir = temp_dir("single");
+    let out = dir.join("out");
+    let out = out.to_str().unwrap();
+
+    // Relative input, its directories are not mirrored under the output directory
+    lz4(&dir, &["src/sub/data.txt", "--output-dir", out]);
+    assert!(dir.join("out").join("data.txt.lz4").is_file());
+    assert!(!dir.join("out").join("src").exists());
+
+    // Absolute input, not written next to the source
+    fs::remove_dir_all(dir.join("out")).unwrap();
+    let input = dir.join("src").join("sub").join("data.txt");
+    lz4(&dir, &[input.to_str().unwrap(), "--output-dir", out]);
+    assert!(dir.join("out").join("data.txt.lz4").is_file());
+    assert!(!dir.join("src").join("sub").join("data.txt.lz4").exists());
+
+    // And back
+    lz4(
+        &dir.join("out"),
+        &["-d", "data.txt.lz4", "--output-dir", "plain"],
+    );
+    assert_eq!(
+        fs::read(dir.join("out").join("plain").join("data.txt")).unwrap(),
+        b"some data to compress"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_levels() {
+    let dir = temp_dir("levels");
+    let data = text();
+    let compressed = |level: &str| {
+        let output = run(&dir, &[level, "-c"], &data);
+        assert!(output.status.success());
+        assert_eq!(run(&dir, &["-d", "-c"], &output.stdout).stdout, data);
+        output.stdout.len()
+    };
+    let default = compressed("-1");
+    assert!(compressed("-12") < default);
+    assert!(compressed("--fast=20") > default);
+    // Levels above the maximum are capped
+    assert_eq!(compressed("-99"), compressed("-12"));
+
+    let output = run(&dir, &["--fast=0", "-c"], &data);
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_block_options() {
+    let dir = temp_dir("blocks");
+    // Frame descriptor: FLG at offset 4 flags independent blocks, BD at offset 5 holds the
+    // block size id
+    let header = |args: &[&str]| {
+        let output = run(&dir, args, b"some data");
+        assert!(output.status.success());
+        (output.stdout[4] & 0x20 != 0, output.stdout[5] >> 4 & 7)
+    };
+    assert_eq!(header(&["-c"]), (true, 7));
+    assert_eq!(header(&["-B4", "-c"]), (true, 4));
+    assert_eq!(header(&["-B5BD", "-c"]), (false, 5));
+    assert_eq!(header(&["-BD", "-B6", "-c"]), (false, 6));
+
+    for args in [&["-B8", "-c"][..], &["-B3", "-c"], &["-B", "-c"]].iter() {
+        let output = run(&dir, args, b"some data");
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).starts_with("lz4: "));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_stdout_force_remove() {
+    let dir = temp_dir("force");
+    let input = dir.join("src").join("sub").join("data.txt");
+    let compressed = dir.join("src").join("sub").join("data.txt.lz4");
+
+    // -c leaves no file behind
+    let output = run(&dir, &["-c", "src/sub/data.txt"], b"");
+    assert!(output.status.success());
+    assert!(!compressed.exists());
+    assert_eq!(
+        run(&dir, &["-d"], &output.stdout).stdout,
+        b"some data to compress"
+    );
+
+    // An existing output is kept unless forced
+    fs::write(&compressed, b"precious").unwrap();
+    let output = run(&dir, &["src/sub/data.txt"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert_eq!(fs::read(&compressed).unwrap(), b"precious");
+    let output = run(&dir, &["-f", "src/sub/data.txt"], b"");
+    assert!(output.status.success());
+    assert_ne!(fs::read(&compressed).unwrap(), b"precious");
+
+    // --rm removes the source once the output is written, -k keeps it
+    lz4(&dir, &["-f", "--rm", "src/sub/data.txt"]);
+    assert!(!input.exists());
+    lz4(&dir, &["-d", "-k", "src/sub/data.txt.lz4"]);
+    assert!(compressed.exists());
+    assert_eq!(fs::read(&input).unwrap(), b"some data to compress");
+    lz4(&dir, &["-d", "-f", "--rm", "src/sub/data.txt.lz4"]);
+    assert!(!compressed.exists());
+    assert_eq!(fs::read(&input).unwrap(), b"some data to compress");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pipe_mode() {
+    let dir = temp_dir("pipe");
+    let data = text();
+    // No file name, or - for standard input
+    for args in [&[][..], &["-"], &["-z", "-", "stdout"]].iter() {
+        let compressed = run(&dir, args, &data);
+        assert!(compressed.status.success());
+        assert_eq!(&compressed.stdout[..4], &[0x04, 0x22, 0x4D, 0x18]);
+        let decompressed = run(&dir, &["-d", "-", "-"], &compressed.stdout);
+        assert!(decompressed.status.success());
+        assert_eq!(decompressed.stdout, data);
+    }
+    // Decompression from standard input needs -d
+    let compressed = run(&dir, &[], &data).stdout;
+    assert_eq!(run(&dir, &["-dc"], &compressed).stdout, data);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = temp_dir("exit");
+    // 0 on success, 1 for bad usage, otherwise the number of files which failed
+    assert_eq!(run(&dir, &["-h"], b"").status.code(), Some(0));
+    assert_eq!(run(&dir, &["-V"], b"").status.code(), Some(0));
+    assert_eq!(run(&dir, &["--bogus"], b"").status.code(), Some(1));
+    assert_eq!(run(&dir, &["a", "b", "c"], b"").status.code(), Some(1));
+    assert_eq!(run(&dir, &["missing"], b"").status.code(), Some(1));
+    assert_eq!(
+        run(&dir, &["-m", "missing", "src/sub/data.txt", "other"], b"")
+            .status
+            .code(),
+        Some(2)
+    );
+    assert!(dir.join("src").join("sub").join("data.txt.lz4").is_file());
+    // Not an lz4 stream
+    assert_eq!(
+        run(&dir, &["-d", "-c"], b"plain text").status.code(),
+        Some(1)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_integrity_check() {
+    let dir = temp_dir("test");
+    let compressed = run(&dir, &["-c"], &text()).stdout;
+    fs::write(dir.join("good.lz4"), &compressed).unwrap();
+    fs::write(
+        dir.join("truncated.lz4"),
+        &compressed[..compressed.len() - 10],
+    )
+    .unwrap();
+    let mut corrupted = compressed.clone();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0xFF;
+    fs::write(dir.join("corrupted.lz4"), &corrupted).unwrap();
+
+    let output = run(&dir, &["-t", "good.lz4"], b"");
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(!dir.join("good").exists());
+    for name in ["truncated.lz4", "corrupted.lz4"].iter() {
+        let output = run(&dir, &["-t", name], b"");
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        assert!(String::from_utf8_lossy(&output.stderr).contains(name));
+    }
+    // Every file is checked, the exit code counts the bad ones
+    let output = run(
+        &dir,
+        &["-t", "truncated.lz4", "good.lz4", "corrupted.lz4"],
+        b"",
+    );
+    assert_eq!(output.status.code(), Some(2));
+    // Standard input too
+    assert!(run(&dir, &["-t"], &compressed).status.success());
+    assert_eq!(run(&dir, &["-t"], &corrupted).status.code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_list() {
+    let dir = temp_dir("list");
+    let data = vec![b'x'; 500];
+    // Without content size, so the sizes come from decoding the frames
+    let frame = run(&dir, &["-c"], &data).stdout;
+    let mut frames = frame.clone();
+    frames.extend_from_slice(&run(&dir, &["-c", "-B4BD"], &data).stdout);
+    fs::write(dir.join("one.lz4"), &frame).unwrap();
+    fs::write(dir.join("two.lz4"), &frames).unwrap();
+    let args = [
+        "-c",
+        "--content-size",
+        "-B5",
+        "--no-frame-crc",
+        "src/sub/data.txt",
+    ];
+    let sized = run(&dir, &args, b"");
+    fs::write(dir.join("sized.lz4"), &sized.stdout).unwrap();
+
+    let output = run(&dir, &["--list", "one.lz4", "two.lz4", "sized.lz4"], b"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows.len(), 3);
+    // Frames, block, checksums, compressed and uncompressed size with their unit, ratio, name
+    assert_eq!(rows[0][..3], ["1", "B7I", "content"]);
+    assert_eq!(rows[0][3..5], [frame.len().to_string().as_str(), "B"]);
+    assert_eq!(rows[0][5..7], ["500", "B"]);
+    assert_eq!(rows[0][8], "one.lz4");
+    // Frames with different settings
+    assert_eq!(rows[1][..3], ["2", "-", "content"]);
+    assert_eq!(rows[1][5..7], ["1000", "B"]);
+    assert_eq!(rows[1][8], "two.lz4");
+    assert_eq!(rows[2][..3], ["1", "B5I", "-"]);
+    assert_eq!(rows[2][5..7], ["21", "B"]);
+
+    // Damaged files are reported without stopping the listing
+    fs::write(dir.join("bad.lz4"), &frames[..frames.len() - 3]).unwrap();
+    let output = run(&dir, &["--list", "bad.lz4", "one.lz4"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_bench() {
+    let dir = temp_dir("bench");
+    fs::write(dir.join("text"), text()).unwrap();
+    let output = run(&dir, &["-b1", "-e2", "-i1", "text"], b"");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Vec<&str>> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    let apis: Vec<(&str, &str)> = rows.iter().map(|row| (row[0], row[1])).collect();
+    assert_eq!(
+        apis,
+        [
+            ("-1", "block"),
+            ("-1", "frame"),
+            ("-2", "block"),
+            ("-2", "frame")
+        ]
+    );
+    for row in rows.iter() {
+        assert!(row[2].ends_with('x'));
+        assert_eq!(row[row.len() - 1], "text");
+    }
+    // Nothing is written next to the input
+    assert!(!dir.join("text.lz4").exists());
+    assert_eq!(
+        run(&dir, &["-b1", "-i1", "missing"], b"").status.code(),
+        Some(1)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_pipe_output_clean() {
+    let dir = temp_dir("clean");
+    let data = text();
+    for args in [&[][..], &["-q"], &["-v"]].iter() {
+        // lz4 | lz4 -d
+        let mut compress = Command::new(env!("CARGO_BIN_EXE_lz4"))
+            .current_dir(&dir)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = compress.stdin.take().unwrap();
+        let input = data.clone();
+        let writer = thread::spawn(move || stdin.write_all(&input).unwrap());
+        let decompress = Command::new(env!("CARGO_BIN_EXE_lz4"))
+            .current_dir(&dir)
+            .args(*args)
+            .arg("-d")
+            .stdin(Stdio::from(compress.stdout.take().unwrap()))
+            .output()
+            .unwrap();
+        writer.join().unwrap();
+        let compress = compress.wait_with_output().unwrap();
+        assert!(compress.status.success());
+        assert!(decompress.status.success());
+        assert!(decompress.stdout == data, "stdout holds more than the data");
+
+        // Only asked for messages, always on stderr
+        let stderr = String::from_utf8(compress.stderr).unwrap()
+            + &String::from_utf8(decompress.stderr).unwrap();
+        if args.contains(&"-v") {
+            assert!(stderr.contains("*** LZ4 command line interface"));
+            assert!(stderr.contains("Compressed"));
+            assert!(stderr.contains("decoded"));
+        } else {
+            assert_eq!(stderr, "");
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_verbosity() {
+    let dir = temp_dir("verbosity");
+    let stderr = |flag: &str| {
+        let mut args = vec!["-f", "src/sub/data.txt"];
+        if !flag.is_empty() {
+            args.insert(0, flag);
+        }
+        let output = run(&dir, &args, b"");
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        String::from_utf8(output.stderr).unwrap()
+    };
+    // Results by default, details with -v, nothing with -q
+    let default = stderr("");
+    assert!(default.starts_with("Compressed 21 bytes into"));
+    assert!(!default.contains("***"));
+    let verbose = stderr("-v");
+    assert!(verbose.starts_with("*** LZ4 command line interface"));
+    assert!(verbose.contains("Compressed 21 bytes into"));
+    assert_eq!(stderr("-q"), "");
+
+    // Errors stay visible with -q, -qq silences them
+    let output = run(&dir, &["-q", "missing"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("missing"));
+    let output = run(&dir, &["-qq", "missing"], b"");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stderr.is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}